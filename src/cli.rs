@@ -0,0 +1,589 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use clap::ArgMatches;
+use directories::ProjectDirs;
+
+use rpfm_lib::packfile::{PFHFileType, PFHVersion};
+
+use wh2_luadb::config::{
+    Config, EmptyOptionalPolicy, MissingPackfilePolicy, OutputLayout, OutputSinkKind,
+    OverwritePolicy, PackSettings,
+};
+use wh2_luadb::game::{find_game_profile, GameProfile, DEFAULT_GAME};
+use wh2_luadb::log::Log;
+use wh2_luadb::output_writer::find_output_writer;
+use wh2_luadb::rpfm::Rpfm;
+use wh2_luadb::steam::{self, PackfileResolver};
+use wh2_luadb::Wh2LuaError;
+
+/// Builds the library config from the command line arguments of a subcommand.
+///
+/// Arguments a subcommand does not define are simply treated as absent.
+pub struct ArgsParser;
+
+impl ArgsParser {
+    pub fn parse_config(matches: &ArgMatches) -> Result<Config, Wh2LuaError> {
+        Log::info("Parsing config...");
+
+        let on_missing = Self::parse_on_missing_arg(matches)?;
+
+        let game = Self::parse_game_arg(matches)?;
+
+        let packfile_paths = Self::try_load_packfile_paths(matches, game, on_missing)?;
+
+        // Packfiles get priority. Only look at in_dir if we're not working with packfile(s)
+        let in_dir_path = if packfile_paths.is_none() {
+            Self::try_parse_in_dir_arg(matches)?
+        } else {
+            None
+        };
+
+        let sink = Self::parse_sink_arg(matches)?;
+
+        let out_dir_path = Self::calculate_out_dir(matches, &packfile_paths, &in_dir_path)?;
+
+        let script_check = matches.value_of("script-check").map(str::to_string);
+
+        let mod_core_prefix = matches.value_of("core-prefix").map(str::to_string);
+
+        let base_mod = matches.is_present("base-data");
+
+        let overwrite = Self::parse_overwrite_arg(matches);
+
+        let strict = matches.is_present("strict");
+
+        let format = matches.value_of("format").unwrap_or("lua").to_string();
+        // Fail early on unknown formats
+        find_output_writer(&format)?;
+
+        let layout = Self::parse_layout_args(matches)?;
+
+        let pack = Self::parse_pack_args(matches, game)?;
+
+        let empty_optional = Self::parse_empty_optional_args(matches);
+        let omit_defaults = matches.is_present("omit-defaults");
+
+        let reverse_indexes = Self::parse_reverse_index_args(matches)?;
+
+        let chunk_rows = Self::parse_positive_number_arg(matches, "chunk-rows")?;
+        let chunk_bytes = Self::parse_positive_number_arg(matches, "chunk-bytes")?;
+
+        let minify = matches.is_present("minify");
+
+        let intern_strings = Self::parse_positive_number_arg(matches, "intern-strings")?;
+
+        let schema = Rpfm::load_schema(game.name)?;
+
+        let table_names = Rpfm::db_table_names(&schema);
+        for table in reverse_indexes.keys() {
+            if !table_names.contains(table) {
+                Log::warning(&format!(
+                    "--reverse-index: table {} is not defined in the schema, no reverse index will be generated for it",
+                    table
+                ));
+            }
+        }
+
+        Log::info("Config OK");
+
+        Ok(Config {
+            schema,
+            packfiles: packfile_paths,
+            in_dir: in_dir_path,
+            out_dir: out_dir_path,
+            script_check,
+            mod_core_prefix,
+            base_mod,
+            overwrite,
+            on_missing,
+            strict,
+            sink,
+            format,
+            layout,
+            pack,
+            empty_optional,
+            omit_defaults,
+            reverse_indexes,
+            chunk_rows,
+            chunk_bytes,
+            minify,
+            intern_strings,
+        })
+    }
+
+    /// Parses only the arguments that determine where the packfile or zip archive is generated (the sink, output
+    /// directory and pack settings), for --restore-backup. Unlike `parse_config`, this never loads the schema or
+    /// resolves the mods in a KMM profile, so a backup can be restored while offline, or while a mod is missing.
+    pub fn parse_restore_backup_args(
+        matches: &ArgMatches,
+    ) -> Result<(OutputSinkKind, PathBuf, PackSettings), Wh2LuaError> {
+        let game = Self::parse_game_arg(matches)?;
+
+        let packfile_paths = match matches.value_of("packfile") {
+            Some(packfile) => Self::try_parse_single_packfile_path_from_arg(packfile)?
+                .map(|packfile| vec![packfile]),
+            None => None,
+        };
+        let in_dir_path = if packfile_paths.is_none() {
+            Self::try_parse_in_dir_arg(matches)?
+        } else {
+            None
+        };
+
+        let sink = Self::parse_sink_arg(matches)?;
+        let out_dir_path = Self::calculate_out_dir(matches, &packfile_paths, &in_dir_path)?;
+        let pack = Self::parse_pack_args(matches, game)?;
+        Ok((sink, out_dir_path, pack))
+    }
+
+    /// Returns the profile of the game selected with --game, or of Warhammer 2 by default
+    pub fn parse_game_arg(matches: &ArgMatches) -> Result<&'static GameProfile, Wh2LuaError> {
+        let game = find_game_profile(matches.value_of("game").unwrap_or(DEFAULT_GAME))?;
+        if matches.is_present("game") {
+            Log::info(&format!("Selected game: {}", game.display_name));
+        }
+        Ok(game)
+    }
+
+    fn parse_on_missing_arg(matches: &ArgMatches) -> Result<MissingPackfilePolicy, Wh2LuaError> {
+        match matches.value_of("on-missing") {
+            None | Some("skip") => Ok(MissingPackfilePolicy::Skip),
+            Some("fail") => Ok(MissingPackfilePolicy::Fail),
+            Some(other) => Err(Wh2LuaError::ConfigError(format!(
+                "Unknown --on-missing policy: {} (expected skip or fail)",
+                other
+            ))),
+        }
+    }
+
+    fn parse_layout_args(matches: &ArgMatches) -> Result<OutputLayout, Wh2LuaError> {
+        let mut layout = OutputLayout::default();
+        if let Some(root) = matches.value_of("layout-root") {
+            layout.root = root.to_string();
+        }
+        if let Some(core_folder) = matches.value_of("core-folder") {
+            layout.core_folder = core_folder.to_string();
+        }
+        if let Some(mod_core_folder) = matches.value_of("mod-core-folder") {
+            layout.mod_core_folder = mod_core_folder.to_string();
+        }
+        if let Some(mod_folder) = matches.value_of("mod-folder") {
+            layout.mod_folder = mod_folder.to_string();
+        }
+        if let Some(file_template) = matches.value_of("file-template") {
+            // Without the table and file name, the db files of different tables (e.g. the data__ files of a packfile),
+            // or all db files of a table, would end up in the same script
+            for placeholder in &["{table}", "{file}"] {
+                if !file_template.contains(placeholder) {
+                    return Err(Wh2LuaError::ConfigError(format!(
+                        "The --file-template must contain the {} placeholder: {}",
+                        placeholder, file_template
+                    )));
+                }
+            }
+            layout.file_template = file_template.to_string();
+        }
+        if let Some(pack_script_root) = matches.value_of("pack-script-root") {
+            layout.pack_script_root = pack_script_root.to_string();
+        }
+        Ok(layout)
+    }
+
+    fn parse_pack_args(
+        matches: &ArgMatches,
+        game: &GameProfile,
+    ) -> Result<PackSettings, Wh2LuaError> {
+        let mut pack = PackSettings::default();
+        if let Some(name) = matches.value_of("pack-name") {
+            if !name.ends_with(".pack") {
+                return Err(Wh2LuaError::ConfigError(format!(
+                    "The --pack-name must end with .pack: {}",
+                    name
+                )));
+            }
+            pack.name = name.to_string();
+        }
+        pack.file_type = match matches.value_of("pack-type") {
+            None | Some("movie") => PFHFileType::Movie,
+            Some("mod") => PFHFileType::Mod,
+            Some(other) => {
+                return Err(Wh2LuaError::ConfigError(format!(
+                    "Unknown --pack-type: {} (expected movie or mod)",
+                    other
+                )))
+            }
+        };
+        pack.version = match matches.value_of("pack-version") {
+            None => game.pack_version,
+            Some("pfh6") => PFHVersion::PFH6,
+            Some("pfh5") => PFHVersion::PFH5,
+            Some("pfh4") => PFHVersion::PFH4,
+            Some("pfh3") => PFHVersion::PFH3,
+            Some(other) => {
+                return Err(Wh2LuaError::ConfigError(format!(
+                    "Unknown --pack-version: {} (expected pfh6, pfh5, pfh4 or pfh3)",
+                    other
+                )))
+            }
+        };
+        Ok(pack)
+    }
+
+    fn parse_empty_optional_args(matches: &ArgMatches) -> EmptyOptionalPolicy {
+        if let Some(sentinel) = matches.value_of("empty-sentinel") {
+            EmptyOptionalPolicy::Sentinel(sentinel.to_string())
+        } else if matches.value_of("empty-optional") == Some("nil") {
+            EmptyOptionalPolicy::Nil
+        } else {
+            EmptyOptionalPolicy::Empty
+        }
+    }
+
+    /// Parses the TABLE:COLUMN values of --reverse-index into the columns to index per table
+    fn parse_reverse_index_args(
+        matches: &ArgMatches,
+    ) -> Result<BTreeMap<String, Vec<String>>, Wh2LuaError> {
+        let mut reverse_indexes: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for value in matches.values_of("reverse-index").into_iter().flatten() {
+            match value.split_once(':') {
+                Some((table, column)) if !table.is_empty() && !column.is_empty() => {
+                    let columns = reverse_indexes.entry(table.to_string()).or_default();
+                    if !columns.iter().any(|c| c == column) {
+                        columns.push(column.to_string());
+                    }
+                }
+                _ => {
+                    return Err(Wh2LuaError::ConfigError(format!(
+                        "Invalid --reverse-index: {} (expected TABLE:COLUMN)",
+                        value
+                    )))
+                }
+            }
+        }
+        Ok(reverse_indexes)
+    }
+
+    /// Parses a numeric option (e.g. --chunk-rows), which must be a positive number
+    fn parse_positive_number_arg(
+        matches: &ArgMatches,
+        arg: &str,
+    ) -> Result<Option<usize>, Wh2LuaError> {
+        match matches.value_of(arg) {
+            None => Ok(None),
+            Some(value) => match value.parse::<usize>() {
+                Ok(size) if size > 0 => Ok(Some(size)),
+                _ => Err(Wh2LuaError::ConfigError(format!(
+                    "Invalid --{}: {} (expected a positive number)",
+                    arg, value
+                ))),
+            },
+        }
+    }
+
+    fn parse_overwrite_arg(matches: &ArgMatches) -> OverwritePolicy {
+        if matches.is_present("clean") {
+            OverwritePolicy::Clean
+        } else if matches.is_present("merge") || matches.is_present("force") {
+            OverwritePolicy::Merge
+        } else {
+            OverwritePolicy::Fail
+        }
+    }
+
+    fn parse_sink_arg(matches: &ArgMatches) -> Result<OutputSinkKind, Wh2LuaError> {
+        if matches.is_present("unpacked") {
+            return Ok(OutputSinkKind::Directory);
+        }
+        match matches.value_of("sink") {
+            None | Some("pack") => Ok(OutputSinkKind::Packfile),
+            Some("dir") => Ok(OutputSinkKind::Directory),
+            Some("zip") => Ok(OutputSinkKind::Zip),
+            Some("stdout") => Ok(OutputSinkKind::Stdout),
+            Some(other) => Err(Wh2LuaError::ConfigError(format!(
+                "Unknown --sink: {} (expected pack, dir, zip or stdout)",
+                other
+            ))),
+        }
+    }
+
+    fn try_load_packfile_paths(
+        matches: &ArgMatches,
+        game: &GameProfile,
+        on_missing: MissingPackfilePolicy,
+    ) -> Result<Option<Vec<PathBuf>>, Wh2LuaError> {
+        Log::debug("Trying to load packfile paths...");
+        let packfile_path_arg = matches.value_of("packfile");
+        let in_dir_path_arg = matches.value_of("input-directory");
+
+        let packfile_paths = if packfile_path_arg.is_none() && in_dir_path_arg.is_none() {
+            Self::try_load_packfile_names_from_kmm_profile(matches, game, on_missing)?
+        } else if let Some(packfile) = packfile_path_arg {
+            Self::try_parse_single_packfile_path_from_arg(packfile)?.map(|packfile| vec![packfile])
+        } else {
+            None
+        };
+
+        Ok(packfile_paths)
+    }
+
+    fn try_load_packfile_names_from_kmm_profile(
+        matches: &ArgMatches,
+        game: &GameProfile,
+        on_missing: MissingPackfilePolicy,
+    ) -> Result<Option<Vec<PathBuf>>, Wh2LuaError> {
+        let profile_name = matches.value_of("kmm-profile").unwrap_or("LastUsedMods");
+        Log::debug(&format!(
+            "Looking for packfile paths in KMM profile {}...",
+            profile_name
+        ));
+        let mut packfiles: Vec<PathBuf> = Vec::new();
+
+        let mut kmm_profile_file = Self::calculate_kmm_profiles_dir(matches, game)?;
+        kmm_profile_file.push(format!("profile_{}.txt", profile_name));
+
+        if !kmm_profile_file.exists() {
+            return Err(Wh2LuaError::ConfigError(format!(
+                "No packfile or input dir specified, and KMM profile file cannot be found: {}",
+                kmm_profile_file.display()
+            )));
+        }
+
+        let mut packfiles_names: Vec<String>;
+        {
+            let file = fs::File::open(kmm_profile_file)?;
+            let reader = BufReader::new(&file);
+            packfiles_names = reader.lines().collect::<Result<_, _>>()?;
+        }
+        packfiles_names.reverse();
+
+        let resolver = Self::create_packfile_resolver(matches, game);
+
+        for name in packfiles_names {
+            Log::debug(&format!("Packfile in KMM profile: {}", name));
+            match resolver.resolve(&name) {
+                Some(packfile_path) => {
+                    Log::info(&format!(
+                        "Mod {} - using packfile {}",
+                        name,
+                        packfile_path.display()
+                    ));
+                    packfiles.push(packfile_path);
+                }
+                None => {
+                    let message = format!(
+                        "Mod {} - packfile could not be found in the data folder or in any workshop folder",
+                        name
+                    );
+                    if on_missing == MissingPackfilePolicy::Fail {
+                        return Err(Wh2LuaError::ConfigError(message));
+                    }
+                    Log::warning(&format!("{}. The mod will be skipped.", message));
+                    Log::add_skipped_packfile(format!("{} (not found)", name));
+                }
+            }
+        }
+
+        return if packfiles.len() == 0 {
+            Ok(None)
+        } else {
+            Ok(Some(packfiles))
+        };
+    }
+
+    /// Creates a resolver that looks for packfiles in the data folder and in the workshop folders of all known Steam libraries.
+    fn create_packfile_resolver(matches: &ArgMatches, game: &GameProfile) -> PackfileResolver {
+        let steam_dir = matches.value_of("steam-dir").map(PathBuf::from);
+        let extra_libraries: Vec<PathBuf> = matches
+            .values_of("steam-library")
+            .map(|values| values.map(PathBuf::from).collect())
+            .unwrap_or_default();
+
+        let library_roots = steam::find_library_roots(steam_dir.as_deref(), &extra_libraries);
+        for library_root in &library_roots {
+            Log::debug(&format!("Steam library: {}", library_root.display()));
+        }
+
+        PackfileResolver::new(PathBuf::from("data"), &library_roots, game.steam_app_id)
+    }
+
+    /// Returns the directory containing the KMM profile files, either from the --kmm-dir argument or from KMM's default location.
+    fn calculate_kmm_profiles_dir(
+        matches: &ArgMatches,
+        game: &GameProfile,
+    ) -> Result<PathBuf, Wh2LuaError> {
+        if let Some(kmm_dir) = matches.value_of("kmm-dir") {
+            let kmm_dir_path = PathBuf::from(kmm_dir);
+            if !kmm_dir_path.is_dir() {
+                return Err(Wh2LuaError::ConfigError(format!(
+                    "KMM profiles directory with specified path not found: {}",
+                    kmm_dir_path.display()
+                )));
+            }
+            return Ok(kmm_dir_path);
+        }
+
+        match ProjectDirs::from("", "", "Kaedrin Mod Manager") {
+            Some(dirs) => Ok([
+                dirs.config_dir().parent().unwrap(),
+                &Path::new("Profiles"),
+                &Path::new(game.kmm_profiles_folder),
+            ]
+            .iter()
+            .collect()),
+            None => Err(Wh2LuaError::ConfigError(
+                "No packfile or input dir specified, and KMM profiles dir cannot be found"
+                    .to_string(),
+            )),
+        }
+    }
+
+    fn try_parse_single_packfile_path_from_arg(
+        packfile_path_str: &str,
+    ) -> Result<Option<PathBuf>, Wh2LuaError> {
+        Log::debug("Parsing packfile from arguments...");
+        let packfile_path = PathBuf::from(packfile_path_str);
+        if !packfile_path.exists() {
+            return Err(Wh2LuaError::ConfigError(format!(
+                "Packfile with specified path not found: {}",
+                packfile_path.display()
+            )));
+        }
+        Ok(Some(packfile_path))
+    }
+
+    fn try_parse_in_dir_arg(matches: &ArgMatches) -> Result<Option<PathBuf>, Wh2LuaError> {
+        Log::debug("Trying to parse input directory from arguments...");
+        if let Some(directory) = matches.value_of("input-directory") {
+            let in_dir_path = PathBuf::from(directory);
+            if !in_dir_path.exists() {
+                return Err(Wh2LuaError::ConfigError(format!(
+                    "Input directory with specified path not found: {}",
+                    in_dir_path.display()
+                )));
+            }
+            Ok(Some(in_dir_path))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn calculate_out_dir(
+        matches: &ArgMatches,
+        packfile_paths: &Option<Vec<PathBuf>>,
+        in_dir_path: &Option<PathBuf>,
+    ) -> Result<PathBuf, Wh2LuaError> {
+        Log::debug("Calculating output directory...");
+        if let Some(output_dir) = matches.value_of("output-directory") {
+            Ok(PathBuf::from(output_dir))
+        } else {
+            // If there is only a single packfile specified, use its name as the output directory
+            if packfile_paths.is_some() && packfile_paths.as_ref().unwrap().len() == 1 {
+                let packfile = packfile_paths.as_ref().unwrap().get(0).unwrap();
+                Ok(Self::generate_output_directory_from_packfile(packfile)?)
+            } else {
+                // If an input directory is specified without output directory, use the input diretory as output directory
+                if let Some(ref in_dir) = in_dir_path {
+                    Log::debug(&format!("Outpt directory not specified in config/arguments, using same as input directory): {}", in_dir.to_str().unwrap()));
+                    Ok(in_dir.clone())
+                } else {
+                    // Fallback: use ./lua_db_export directory
+                    Log::debug(&format!(
+                        "Output directory not specified, using .\\lua_db_export"
+                    ));
+                    let mut path = std::env::current_dir()?;
+                    path.push("lua_db_export");
+                    Ok(path)
+                }
+            }
+        }
+    }
+
+    fn generate_output_directory_from_packfile(
+        packfile_path: &PathBuf,
+    ) -> Result<PathBuf, Wh2LuaError> {
+        let packfile_dir = packfile_path.parent().unwrap();
+        let packfile_name = packfile_path.file_stem().unwrap();
+        let mut dir = PathBuf::from(packfile_dir);
+        dir.push(&format!("{0}_lua_ext", packfile_name.to_str().unwrap()));
+        Log::debug(&format!(
+            "Output directory (derived from packfile name): {}",
+            dir.to_str().unwrap()
+        ));
+        Ok(dir)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_support::{write_file, TestDir};
+
+    use clap::{load_yaml, App};
+
+    /// Resolves the packfiles of the KMM profile "test" in the fixture directory (see `kmm_fixture`)
+    fn resolve_kmm_profile(
+        fixture_dir: &Path,
+        on_missing: MissingPackfilePolicy,
+    ) -> Result<Option<Vec<PathBuf>>, Wh2LuaError> {
+        let yaml = load_yaml!("cli.yaml");
+        let matches = App::from(yaml).get_matches_from(vec![
+            "wh2-luadb",
+            "generate",
+            "--kmm-profile",
+            "test",
+            "--kmm-dir",
+            fixture_dir.join("kmm").to_str().unwrap(),
+            "--steam-library",
+            fixture_dir.join("library").to_str().unwrap(),
+        ]);
+        let (_, generate_matches) = matches.subcommand().unwrap();
+        let game = find_game_profile(DEFAULT_GAME).unwrap();
+        ArgsParser::try_load_packfile_names_from_kmm_profile(generate_matches, game, on_missing)
+    }
+
+    /// A KMM profile with two mods from the workshop folder of a Steam library, and one mod that cannot be found
+    fn kmm_fixture(name: &str) -> (TestDir, PathBuf) {
+        let dir = TestDir::new(name);
+        write_file(
+            &dir.path().join("kmm").join("profile_test.txt"),
+            "first_mod.pack\nmissing_mod.pack\nsecond_mod.pack",
+        );
+        let workshop_dir = dir
+            .path()
+            .join("library")
+            .join("steamapps")
+            .join("workshop")
+            .join("content")
+            .join("594570");
+        write_file(&workshop_dir.join("1001").join("first_mod.pack"), "");
+        write_file(&workshop_dir.join("1002").join("second_mod.pack"), "");
+        (dir, workshop_dir)
+    }
+
+    #[test]
+    fn resolves_kmm_profile_from_workshop_folders() {
+        let (dir, workshop_dir) = kmm_fixture("kmm_profile");
+
+        let packfiles = resolve_kmm_profile(dir.path(), MissingPackfilePolicy::Skip).unwrap();
+        // The load order is the reverse of the order in the profile, and the missing mod is skipped
+        assert_eq!(
+            packfiles,
+            Some(vec![
+                workshop_dir.join("1002").join("second_mod.pack"),
+                workshop_dir.join("1001").join("first_mod.pack"),
+            ])
+        );
+    }
+
+    #[test]
+    fn fails_on_missing_kmm_profile_packfile() {
+        let (dir, _) = kmm_fixture("kmm_profile_missing");
+
+        let result = resolve_kmm_profile(dir.path(), MissingPackfilePolicy::Fail);
+        assert!(matches!(result, Err(Wh2LuaError::ConfigError(_))));
+    }
+}
@@ -0,0 +1,218 @@
+use std::path::PathBuf;
+
+use clap::ArgMatches;
+
+use rpfm_lib::schema::FieldType;
+
+use wh2_luadb::config::{Config, MissingPackfilePolicy, OutputSinkKind};
+use wh2_luadb::diff::{self, DiffSource};
+use wh2_luadb::game::GameProfile;
+use wh2_luadb::log::Log;
+use wh2_luadb::rpfm::Rpfm;
+use wh2_luadb::Wh2LuaError;
+
+use crate::cli::ArgsParser;
+
+/// Each command returns the path of the game executable to start when done, if any.
+pub type CommandResult = Result<Option<PathBuf>, Wh2LuaError>;
+
+/// Generates the output for a packfile, an input directory, or the mods in a KMM profile.
+/// In watch mode, keeps regenerating the output for changed files until stopped.
+/// With --restore-backup, restores the previously generated packfile or zip archive instead.
+pub fn generate(matches: &ArgMatches) -> CommandResult {
+    if matches.is_present("restore-backup") {
+        let (sink, out_dir, pack) = ArgsParser::parse_restore_backup_args(matches)?;
+        wh2_luadb::restore_backup(sink, &out_dir, &pack)?;
+        return Ok(None);
+    }
+
+    let config = ArgsParser::parse_config(matches)?;
+    if matches.is_present("watch") {
+        wh2_luadb::watch::watch(&config)?;
+    } else {
+        wh2_luadb::generate(&config)?;
+    }
+    Ok(None)
+}
+
+/// Generates the packfile for the mods in a KMM profile into the game's data folder (or restores the previously
+/// generated one), and asks for the game to be started.
+pub fn launch(matches: &ArgMatches) -> CommandResult {
+    let game_executable = find_game_executable(ArgsParser::parse_game_arg(matches)?)?;
+
+    let out_dir = PathBuf::from("data");
+    let sink = OutputSinkKind::Packfile;
+
+    if matches.is_present("restore-backup") {
+        let (_, _, pack) = ArgsParser::parse_restore_backup_args(matches)?;
+        wh2_luadb::restore_backup(sink, &out_dir, &pack)?;
+        return Ok(Some(game_executable));
+    }
+
+    let mut config = ArgsParser::parse_config(matches)?;
+    config.out_dir = out_dir;
+    config.sink = sink;
+    wh2_luadb::generate(&config)?;
+    Ok(Some(game_executable))
+}
+
+/// Loads and decodes all db tables in strict mode, without generating any output.
+pub fn validate(matches: &ArgMatches) -> CommandResult {
+    let mut config = ArgsParser::parse_config(matches)?;
+    config.strict = true;
+
+    let preprocessed_packfiles = wh2_luadb::load(&config)?;
+
+    let table_count: usize = preprocessed_packfiles
+        .iter()
+        .map(|(_, tables)| tables.len())
+        .sum();
+    Log::info(&format!(
+        "{} db table(s) from {} source(s) validated",
+        table_count,
+        preprocessed_packfiles.len()
+    ));
+    Ok(None)
+}
+
+/// Updates the schema if needed, and prints either all db table names or the fields of a single table.
+pub fn schema(matches: &ArgMatches) -> CommandResult {
+    let schema = Rpfm::load_schema(ArgsParser::parse_game_arg(matches)?.name)?;
+
+    if let Some(table_name) = matches.value_of("table") {
+        let definition = Rpfm::latest_db_definition(&schema, table_name)?;
+        Log::info(&format!(
+            "{} - definition version {}",
+            table_name,
+            definition.get_version()
+        ));
+        for field in definition.get_fields_processed() {
+            println!(
+                "{}\t{}{}",
+                field.get_name(),
+                field_type_name(field.get_ref_field_type()),
+                if field.get_is_key() { "\tkey" } else { "" }
+            );
+        }
+    } else {
+        let mut table_names = Rpfm::db_table_names(&schema);
+        table_names.sort();
+        Log::info(&format!("{} db tables in schema", table_names.len()));
+        for table_name in table_names {
+            println!("{}", table_name);
+        }
+    }
+    Ok(None)
+}
+
+/// Compares two sources, and prints the added, removed and changed rows (and fields) per db table.
+/// Previously generated output can only be compared to other generated output, and is compared per script file.
+pub fn diff(matches: &ArgMatches) -> CommandResult {
+    let schema = Rpfm::load_schema(ArgsParser::parse_game_arg(matches)?.name)?;
+    let mut config = Config::new(schema, PathBuf::from("."));
+    // Output paths are irrelevant here, so data__ tables should not need a prefix
+    config.base_mod = true;
+    config.on_missing = MissingPackfilePolicy::Fail;
+
+    let old_source =
+        diff::load_diff_source(&config, &PathBuf::from(matches.value_of("old").unwrap()))?;
+    let new_source =
+        diff::load_diff_source(&config, &PathBuf::from(matches.value_of("new").unwrap()))?;
+    let summary = matches.is_present("summary");
+
+    match (old_source, new_source) {
+        (DiffSource::DbTables(old_tables), DiffSource::DbTables(new_tables)) => {
+            let table_diffs = diff::diff_tables(&old_tables, &new_tables);
+            for table_diff in &table_diffs {
+                println!(
+                    "{}: {} added, {} removed, {} changed",
+                    table_diff.table_name,
+                    table_diff.added.len(),
+                    table_diff.removed.len(),
+                    table_diff.changed.len()
+                );
+                if summary {
+                    continue;
+                }
+                for key in &table_diff.added {
+                    println!("  + {}", key);
+                }
+                for key in &table_diff.removed {
+                    println!("  - {}", key);
+                }
+                for (key, field_changes) in &table_diff.changed {
+                    println!("  ~ {}", key);
+                    for field_change in field_changes {
+                        println!(
+                            "      {}: {} -> {}",
+                            field_change.field,
+                            field_change.old_value.as_deref().unwrap_or("(none)"),
+                            field_change.new_value.as_deref().unwrap_or("(none)")
+                        );
+                    }
+                }
+            }
+            Log::info(&format!("{} table(s) with differences", table_diffs.len()));
+        }
+        (DiffSource::GeneratedScripts(old_scripts), DiffSource::GeneratedScripts(new_scripts)) => {
+            let script_diff = diff::diff_scripts(&old_scripts, &new_scripts);
+            println!(
+                "{} added, {} removed, {} changed",
+                script_diff.added.len(),
+                script_diff.removed.len(),
+                script_diff.changed.len()
+            );
+            if !summary {
+                for path in &script_diff.added {
+                    println!("  + {}", path);
+                }
+                for path in &script_diff.removed {
+                    println!("  - {}", path);
+                }
+                for path in &script_diff.changed {
+                    println!("  ~ {}", path);
+                }
+            }
+        }
+        _ => {
+            return Err(Wh2LuaError::ConfigError(
+                "Cannot compare db files to previously generated output. Both sources must contain db files, or both must be generated output.".to_string(),
+            ))
+        }
+    }
+
+    Ok(None)
+}
+
+/// Looks for the game executable in the current directory.
+///
+/// When started through a launcher like wh2-luadb-kmm-launcher, this executable itself takes the place of the game
+/// executable (e.g. Warhammer2.exe), and the actual game has been renamed (e.g. to Warhammer2_real.exe).
+fn find_game_executable(game: &GameProfile) -> Result<PathBuf, Wh2LuaError> {
+    game.executables
+        .iter()
+        .map(|name| PathBuf::from(".").join(name))
+        .find(|path| path.exists())
+        .ok_or_else(|| {
+            Wh2LuaError::ConfigError(format!(
+                "{} executable not found. The launch command must be run from the game's install directory.",
+                game.display_name
+            ))
+        })
+}
+
+fn field_type_name(field_type: &FieldType) -> &'static str {
+    match field_type {
+        FieldType::Boolean => "boolean",
+        FieldType::F32 => "f32",
+        FieldType::I16 => "i16",
+        FieldType::I32 => "i32",
+        FieldType::I64 => "i64",
+        FieldType::StringU8 => "string_u8",
+        FieldType::StringU16 => "string_u16",
+        FieldType::OptionalStringU8 => "optional_string_u8",
+        FieldType::OptionalStringU16 => "optional_string_u16",
+        FieldType::SequenceU16(_) => "sequence_u16",
+        FieldType::SequenceU32(_) => "sequence_u32",
+    }
+}
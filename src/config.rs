@@ -1,228 +1,375 @@
-use std::fs;
-use std::io::{BufRead, BufReader};
-use std::path::{Path, PathBuf};
-
-use clap::ArgMatches;
-use directories::ProjectDirs;
-
-use crate::log::Log;
-use crate::rpfm::Rpfm;
-use crate::Wh2LuaError;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
 
+use rpfm_lib::packfile::{PFHFileType, PFHVersion};
 use rpfm_lib::schema::Schema;
 
-pub struct Config {
-    pub schema: Schema,
-    pub packfiles: Option<Vec<PathBuf>>,
-    pub in_dir: Option<PathBuf>,
-    pub out_dir: PathBuf,
-    pub script_check: Option<String>,
-    pub mod_core_prefix: Option<String>,
-    pub base_mod: bool,
-    pub force: bool,
-    pub write_files_to_disk: bool,
-    pub launch_game: bool,
+/// What to do with packfiles that cannot be found or opened
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissingPackfilePolicy {
+    /// Skip the packfile with a warning, and continue with the rest of the load order
+    Skip,
+    /// Stop with an error
+    Fail,
 }
 
-impl Config {
-    pub fn from_matches(matches: &ArgMatches) -> Result<Config, Wh2LuaError> {
-        Log::info("Parsing config...");
-
-        let packfile_paths = Self::try_load_packfile_paths(matches)?;
-
-        // Packfiles get priority. Only look at in_dir if we're not working with packfile(s)
-        let in_dir_path = if packfile_paths.is_none() {
-            Self::try_parse_in_dir_arg(matches)?
-        } else {
-            None
-        };
-
-        let out_dir_path = Self::calculate_out_dir(matches, &packfile_paths, &in_dir_path)?;
-
-        let script_check = matches.value_of("script-check").map(str::to_string);
-
-        let mod_core_prefix = matches.value_of("core-prefix").map(str::to_string);
-
-        let base_mod = matches.is_present("base-data");
+/// Where the generated files are written to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputSinkKind {
+    /// Directly to disk, in the output directory
+    Directory,
+    /// In a (movie) packfile in the output directory
+    Packfile,
+    /// In a zip archive in the output directory
+    Zip,
+    /// Streamed to stdout
+    Stdout,
+}
 
-        let force = matches.is_present("force");
+/// What to do with the files already in the output directory (only applies to unpacked output)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverwritePolicy {
+    /// Stop with an error if the output directory is not empty
+    Fail,
+    /// Write into the output directory, overwriting files where necessary and leaving all other files alone
+    Merge,
+    /// Write into the output directory, and remove the files generated by previous runs that are no longer generated
+    Clean,
+}
 
-        let write_files_to_disk = matches.is_present("unpacked");
+/// The folder a generated script is placed in, based on the kind of db file it was generated from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptFolder {
+    /// data__ (core) tables of the base game, or of a base mod
+    Core,
+    /// data__ (core) tables of a mod
+    ModCore,
+    /// All other tables
+    Mod,
+}
 
-        let launch_game = Self::calculate_should_launch_game(matches);
+/// How empty values of optional string columns are written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EmptyOptionalPolicy {
+    /// As an empty string, like any other string column
+    Empty,
+    /// As nil, by leaving the field out of the row
+    Nil,
+    /// As the given sentinel string
+    Sentinel(String),
+}
 
-        let game_name = if let Some(name) = matches.value_of("game") {
-            Log::info(&format!("Loading schema for game: {}", name));
-            name
-        } else {
-            "warhammer_2"
-        };
+/// Where the generated scripts are placed, and how they are named.
+#[derive(Debug, Clone)]
+pub struct OutputLayout {
+    /// The root folder of all generated scripts
+    pub root: String,
+    /// Folder name for scripts generated from base data__ tables
+    pub core_folder: String,
+    /// Folder name for scripts generated from mod data__ tables
+    pub mod_core_folder: String,
+    /// Folder name for scripts generated from all other tables
+    pub mod_folder: String,
+    /// Template for the path of each script, with "/" separators. Supports the placeholders {root}, {folder}, {table},
+    /// {pack} (the packfile or input directory name), {file} (the db file name) and {ext} (the output file extension).
+    pub file_template: String,
+    /// The folder in the generated packfile that contains the generated scripts
+    pub pack_script_root: String,
+}
 
-        let schema = Rpfm::load_schema(game_name)?;
+impl Default for OutputLayout {
+    fn default() -> Self {
+        Self {
+            root: "lua_db".to_string(),
+            core_folder: "core".to_string(),
+            mod_core_folder: "mod_core".to_string(),
+            mod_folder: "mod".to_string(),
+            file_template: "{root}/{folder}/{table}/{file}.{ext}".to_string(),
+            pack_script_root: "script".to_string(),
+        }
+    }
+}
 
-        Log::info("Config OK");
+impl OutputLayout {
+    pub fn folder_name(&self, folder: ScriptFolder) -> &str {
+        match folder {
+            ScriptFolder::Core => &self.core_folder,
+            ScriptFolder::ModCore => &self.mod_core_folder,
+            ScriptFolder::Mod => &self.mod_folder,
+        }
+    }
 
-        Ok(Config {
-            schema,
-            packfiles: packfile_paths,
-            in_dir: in_dir_path,
-            out_dir: out_dir_path,
-            script_check,
-            mod_core_prefix,
-            base_mod,
-            force,
-            write_files_to_disk,
-            launch_game,
-        })
+    /// Returns the path of a generated script, as a list of path components, by filling in the file template
+    pub fn script_path(
+        &self,
+        folder: ScriptFolder,
+        table: &str,
+        pack: &str,
+        file: &str,
+        ext: &str,
+    ) -> Vec<String> {
+        self.file_template
+            .replace("{root}", &self.root)
+            .replace("{folder}", self.folder_name(folder))
+            .replace("{table}", table)
+            .replace("{pack}", pack)
+            .replace("{file}", file)
+            .replace("{ext}", ext)
+            .split('/')
+            .filter(|component| !component.is_empty())
+            .map(str::to_string)
+            .collect()
     }
 
-    fn try_load_packfile_paths(matches: &ArgMatches) -> Result<Option<Vec<PathBuf>>, Wh2LuaError> {
-        Log::debug("Trying to load packfile paths...");
-        let packfile_path_arg = matches.value_of("packfile");
-        let in_dir_path_arg = matches.value_of("input-directory");
-
-        let packfile_paths = if packfile_path_arg.is_none() && in_dir_path_arg.is_none() {
-            Self::try_load_packfile_names_from_kmm_last_used_profile()?
-        } else if let Some(packfile) = packfile_path_arg {
-            Self::try_parse_single_packfile_path_from_arg(packfile)?.map(|packfile| vec![packfile])
-        } else {
-            None
+    /// Returns the path of a file generated for a script (e.g. a reverse index), in the given folder directly in the root
+    /// folder: "<root>/<folder>/<script path>", with the suffix appended to the script's file name. Keeping these files
+    /// out of the script folders means loaders going through those folders never mistake them for data. Dots in the
+    /// script path are replaced by underscores, as require treats them as path separators, so distinct scripts can map to
+    /// the same support file (see `claim_support_files`). The root folder is used as is.
+    pub fn support_file_path(
+        &self,
+        folder: &str,
+        script_path: &[String],
+        suffix: &str,
+    ) -> Vec<String> {
+        let root = self.root_file_path(folder);
+        let mut script_path = script_path.to_vec();
+        // Don't repeat the root folder
+        let root_len = root.len() - 1;
+        if script_path.len() > root_len && script_path[..root_len] == root[..root_len] {
+            script_path.drain(..root_len);
+        }
+        let file_name = script_path.pop().unwrap_or_default();
+        // Drop file extension
+        let file_stem = match file_name.rfind('.') {
+            Some(index) => file_name[..index].to_string(),
+            None => file_name,
         };
+        script_path.push(format!("{}{}", file_stem, suffix));
+
+        let mut path: Vec<String> = root
+            .into_iter()
+            .chain(
+                script_path
+                    .into_iter()
+                    .map(|component| component.replace('.', "_")),
+            )
+            .collect();
+        path.last_mut().unwrap().push_str(".lua");
+        path
+    }
 
-        Ok(packfile_paths)
+    /// Returns the path of a file directly in the root folder, e.g. the manifest
+    pub fn root_file_path(&self, file_name: &str) -> Vec<String> {
+        self.root
+            .split('/')
+            .filter(|component| !component.is_empty())
+            .map(str::to_string)
+            .chain(std::iter::once(file_name.to_string()))
+            .collect()
     }
+}
 
-    fn try_load_packfile_names_from_kmm_last_used_profile(
-    ) -> Result<Option<Vec<PathBuf>>, Wh2LuaError> {
-        Log::debug("Looking for packfile paths in KMM last used mods profile...");
-        let mut packfiles: Vec<PathBuf> = Vec::new();
-
-        let kmm_last_used_file: PathBuf = match ProjectDirs::from("", "", "Kaedrin Mod Manager") {
-            Some(dirs) => [
-                dirs.config_dir().parent().unwrap(),
-                &Path::new("Profiles"),
-                &Path::new("Warhammer2"),
-                &Path::new("profile_LastUsedMods.txt"),
-            ]
-            .iter()
-            .collect(),
-            None => {
-                return Err(Wh2LuaError::ConfigError(
-                    "No packfile or input dir specified, and KMM profiles dir cannot be found"
-                        .to_string(),
-                ))
-            }
-        };
+/// How the generated packfile is created
+#[derive(Debug, Clone)]
+pub struct PackSettings {
+    /// The file name of the generated packfile (the zip archive gets the same name, with a .zip extension)
+    pub name: String,
+    /// The packfile type, e.g. Movie (always loaded by the game) or Mod (only loaded when enabled)
+    pub file_type: PFHFileType,
+    /// The packfile format version, which depends on the game (see `GameProfile::pack_version`)
+    pub version: PFHVersion,
+}
 
-        if !kmm_last_used_file.exists() {
-            return Err(Wh2LuaError::ConfigError(
-                "No packfile or input dir specified, and KMM profiles dir cannot be found"
-                    .to_string(),
-            ));
+impl Default for PackSettings {
+    fn default() -> Self {
+        Self {
+            name: "lua_db_generated.pack".to_string(),
+            file_type: PFHFileType::Movie,
+            version: PFHVersion::PFH5,
         }
+    }
+}
 
-        let mut packfiles_names: Vec<String>;
-        {
-            let file = fs::File::open(kmm_last_used_file)?;
-            let reader = BufReader::new(&file);
-            packfiles_names = reader.lines().collect::<Result<_, _>>()?;
-        }
-        packfiles_names.reverse();
+/// Everything the library needs to know to load the input files and generate the output.
+#[derive(Clone)]
+pub struct Config {
+    /// The RPFM schema used to decode db tables (see `Rpfm::load_schema`)
+    pub schema: Schema,
+    /// The packfiles to process, in load order (scripts generated from later packfiles overwrite those from earlier
+    /// ones). Takes priority over `in_dir`.
+    pub packfiles: Option<Vec<PathBuf>>,
+    /// A directory with db files extracted by RPFM, under <in_dir>/db/<table>/<file>
+    pub in_dir: Option<PathBuf>,
+    /// The directory in which the output is generated
+    pub out_dir: PathBuf,
+    /// If set, the generated scripts only return data if this script exists on the VFS
+    pub script_check: Option<String>,
+    /// Prefix for the file names of generated data__ (core) scripts
+    pub mod_core_prefix: Option<String>,
+    /// Treat data__ tables as base game data, placing them in the "core" folder without prefix
+    pub base_mod: bool,
+    /// What to do with the files already in the output directory
+    pub overwrite: OverwritePolicy,
+    /// What to do with packfiles that cannot be found or opened
+    pub on_missing: MissingPackfilePolicy,
+    /// Fail instead of skipping db tables that cannot be processed
+    pub strict: bool,
+    /// Where the generated files are written to
+    pub sink: OutputSinkKind,
+    /// The name of the output format (see `output_writer::output_writers`)
+    pub format: String,
+    /// Where the generated scripts are placed, and how they are named
+    pub layout: OutputLayout,
+    /// How the generated packfile is created
+    pub pack: PackSettings,
+    /// How empty values of optional string columns are written
+    pub empty_optional: EmptyOptionalPolicy,
+    /// Leave out (non-key) fields whose value equals the default value in the schema
+    pub omit_defaults: bool,
+    /// Table name -> the columns to generate reverse indexes on (see `ReverseIndex`)
+    pub reverse_indexes: BTreeMap<String, Vec<String>>,
+    /// Split the script of a table with more rows than this into chunks (see `LuaWriter`)
+    pub chunk_rows: Option<usize>,
+    /// Split the script of a table with more row data (in bytes) than this into chunks
+    pub chunk_bytes: Option<usize>,
+    /// Generate minified scripts, without indentation and whitespace (see `LuaWriter`)
+    pub minify: bool,
+    /// Hoist strings that occur at least this many times in a script into constants at the top of the script
+    pub intern_strings: Option<usize>,
+}
 
-        for name in packfiles_names {
-            Log::debug(&format!("Packfile in KMM last profile: {}", name));
-            packfiles.push([Path::new("data"), Path::new(&name)].iter().collect());
+impl Config {
+    /// Creates a config for the given schema and output directory, with all other options set to their defaults.
+    pub fn new(schema: Schema, out_dir: PathBuf) -> Self {
+        Self {
+            schema,
+            packfiles: None,
+            in_dir: None,
+            out_dir,
+            script_check: None,
+            mod_core_prefix: None,
+            base_mod: false,
+            overwrite: OverwritePolicy::Fail,
+            on_missing: MissingPackfilePolicy::Skip,
+            strict: false,
+            sink: OutputSinkKind::Packfile,
+            format: "lua".to_string(),
+            layout: OutputLayout::default(),
+            pack: PackSettings::default(),
+            empty_optional: EmptyOptionalPolicy::Empty,
+            omit_defaults: false,
+            reverse_indexes: BTreeMap::new(),
+            chunk_rows: None,
+            chunk_bytes: None,
+            minify: false,
+            intern_strings: None,
         }
-
-        return if packfiles.len() == 0 {
-            Ok(None)
-        } else {
-            Ok(Some(packfiles))
-        };
     }
+}
 
-    fn try_parse_single_packfile_path_from_arg(
-        packfile_path_str: &str,
-    ) -> Result<Option<PathBuf>, Wh2LuaError> {
-        Log::debug("Parsing packfile from arguments...");
-        let packfile_path = PathBuf::from(packfile_path_str);
-        if !packfile_path.exists() {
-            return Err(Wh2LuaError::ConfigError(format!(
-                "Packfile with specified path not found: {}",
-                packfile_path.display()
-            )));
-        }
-        Ok(Some(packfile_path))
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    fn try_parse_in_dir_arg(matches: &ArgMatches) -> Result<Option<PathBuf>, Wh2LuaError> {
-        Log::debug("Trying to parse input directory from arguments...");
-        if let Some(directory) = matches.value_of("input-directory") {
-            let in_dir_path = PathBuf::from(directory);
-            if !in_dir_path.exists() {
-                return Err(Wh2LuaError::ConfigError(format!(
-                    "Input directory with specified path not found: {}",
-                    in_dir_path.display()
-                )));
-            }
-            Ok(Some(in_dir_path))
-        } else {
-            Ok(None)
-        }
+    fn strings(components: &[&str]) -> Vec<String> {
+        components
+            .iter()
+            .map(|component| component.to_string())
+            .collect()
     }
 
-    fn calculate_out_dir(
-        matches: &ArgMatches,
-        packfile_paths: &Option<Vec<PathBuf>>,
-        in_dir_path: &Option<PathBuf>,
-    ) -> Result<PathBuf, Wh2LuaError> {
-        Log::debug("Calculating output directory...");
-        if let Some(output_dir) = matches.value_of("output-directory") {
-            Ok(PathBuf::from(output_dir))
-        } else {
-            // If there is only a single packfile specified, use its name as the output directory
-            if packfile_paths.is_some() && packfile_paths.as_ref().unwrap().len() == 1 {
-                let packfile = packfile_paths.as_ref().unwrap().get(0).unwrap();
-                Ok(Self::generate_output_directory_from_packfile(packfile)?)
-            } else {
-                // If an input directory is specified without output directory, use the input diretory as output directory
-                if let Some(ref in_dir) = in_dir_path {
-                    Log::debug(&format!("Outpt directory not specified in config/arguments, using same as input directory): {}", in_dir.to_str().unwrap()));
-                    Ok(in_dir.clone())
-                } else {
-                    // Fallback: use ./lua_db_export directory
-                    Log::debug(&format!(
-                        "Output directory not specified, using .\\lua_db_export"
-                    ));
-                    let mut path = std::env::current_dir()?;
-                    path.push("lua_db_export");
-                    Ok(path)
-                }
-            }
-        }
+    #[test]
+    fn script_path_uses_default_template() {
+        let layout = OutputLayout::default();
+        assert_eq!(
+            layout.script_path(
+                ScriptFolder::Mod,
+                "land_units_tables",
+                "my_mod.pack",
+                "my_units",
+                "lua"
+            ),
+            strings(&["lua_db", "mod", "land_units_tables", "my_units.lua"])
+        );
+        assert_eq!(
+            layout.script_path(
+                ScriptFolder::Core,
+                "land_units_tables",
+                "data.pack",
+                "data__",
+                "lua"
+            ),
+            strings(&["lua_db", "core", "land_units_tables", "data__.lua"])
+        );
     }
 
-    fn generate_output_directory_from_packfile(
-        packfile_path: &PathBuf,
-    ) -> Result<PathBuf, Wh2LuaError> {
-        let packfile_dir = packfile_path.parent().unwrap();
-        let packfile_name = packfile_path.file_stem().unwrap();
-        let mut dir = PathBuf::from(packfile_dir);
-        dir.push(&format!("{0}_lua_ext", packfile_name.to_str().unwrap()));
-        Log::debug(&format!(
-            "Output directory (derived from packfile name): {}",
-            dir.to_str().unwrap()
-        ));
-        Ok(dir)
+    #[test]
+    fn script_path_fills_in_custom_template() {
+        let layout = OutputLayout {
+            root: "my_mod_data/generated".to_string(),
+            mod_core_folder: "mine".to_string(),
+            file_template: "{root}//{folder}/{table}/{pack}__{file}.{ext}".to_string(),
+            ..OutputLayout::default()
+        };
+        // Multi-level roots are split, and empty components are dropped
+        assert_eq!(
+            layout.script_path(
+                ScriptFolder::ModCore,
+                "units_tables",
+                "my_mod",
+                "data__",
+                "json"
+            ),
+            strings(&[
+                "my_mod_data",
+                "generated",
+                "mine",
+                "units_tables",
+                "my_mod__data__.json"
+            ])
+        );
     }
 
-    fn calculate_should_launch_game(matches: &ArgMatches) -> bool {
-        let packfile_path_arg = matches.value_of("packfile");
-        let in_dir_path_arg = matches.value_of("input-directory");
+    #[test]
+    fn support_file_path_is_placed_in_folder_in_root() {
+        let layout = OutputLayout::default();
+        let script_path = strings(&["lua_db", "mod", "land_units_tables", "my.mod_data__.lua"]);
+        assert_eq!(
+            layout.support_file_path("_chunks", &script_path, "__chunk_1"),
+            strings(&[
+                "lua_db",
+                "_chunks",
+                "mod",
+                "land_units_tables",
+                "my_mod_data____chunk_1.lua"
+            ])
+        );
+    }
 
-        packfile_path_arg.is_none()
-            && in_dir_path_arg.is_none()
-            && (Path::new("./Warhammer2.exe").exists()
-                || Path::new("./Warhammer2_real.exe").exists())
+    #[test]
+    fn support_file_path_keeps_dots_in_root() {
+        let layout = OutputLayout {
+            root: "my.mod/lua_db".to_string(),
+            ..OutputLayout::default()
+        };
+        let script_path = strings(&[
+            "my.mod",
+            "lua_db",
+            "mod",
+            "units_tables",
+            "my.mod_data__.lua",
+        ]);
+        assert_eq!(
+            layout.support_file_path("_reverse_indexes", &script_path, "__by_category"),
+            strings(&[
+                "my.mod",
+                "lua_db",
+                "_reverse_indexes",
+                "mod",
+                "units_tables",
+                "my_mod_data____by_category.lua"
+            ])
+        );
     }
 }
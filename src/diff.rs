@@ -0,0 +1,337 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+use walkdir::WalkDir;
+
+use crate::config::Config;
+use crate::log::Log;
+use crate::rpfm::Rpfm;
+use crate::tw_db_pp::{LuaValue, TotalWarDbPreProcessed};
+use crate::wh2_lua_error::Wh2LuaError;
+
+/// One side of a diff: either decoded db tables, or previously generated script files (path -> contents)
+pub enum DiffSource {
+    DbTables(Vec<TotalWarDbPreProcessed>),
+    GeneratedScripts(BTreeMap<Vec<String>, String>),
+}
+
+/// A single field that differs between the old and new version of a row
+pub struct FieldChange {
+    pub field: String,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+}
+
+/// The differences between the old and new version of a single db table, by row key
+pub struct TableDiff {
+    pub table_name: String,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<(String, Vec<FieldChange>)>,
+}
+
+impl TableDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// The differences between two sets of generated script files, by script path
+pub struct ScriptDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+/// Loads one side of a diff.
+///
+/// Packfiles and directories with extracted db files (<dir>/db/<table>/<file>) are loaded as db tables. Packfiles
+/// without any db files, and directories without a db folder, are assumed to be previously generated output, and are
+/// loaded as script files.
+///
+/// Db tables are loaded strictly: a table that cannot be decoded would otherwise show up as all of its rows added or
+/// removed, and a packfile in which no table can be decoded would be compared as generated output.
+pub fn load_diff_source(config: &Config, source: &Path) -> Result<DiffSource, Wh2LuaError> {
+    if !source.exists() {
+        return Err(Wh2LuaError::ConfigError(format!(
+            "Diff source with specified path not found: {}",
+            source.display()
+        )));
+    }
+
+    if source.is_dir() {
+        if source.join("db").is_dir() {
+            Ok(DiffSource::DbTables(load_db_tables(config, source)?))
+        } else {
+            let mut scripts = BTreeMap::new();
+            for entry in WalkDir::new(source).min_depth(1) {
+                let entry = entry.map_err(|error| {
+                    Wh2LuaError::IoError(std::io::Error::new(std::io::ErrorKind::Other, error))
+                })?;
+                if entry.file_type().is_file() {
+                    let path = entry
+                        .path()
+                        .strip_prefix(source)
+                        .unwrap()
+                        .iter()
+                        .map(|component| component.to_string_lossy().to_string())
+                        .collect();
+                    scripts.insert(path, fs::read_to_string(entry.path())?);
+                }
+            }
+            Ok(DiffSource::GeneratedScripts(scripts))
+        }
+    } else {
+        let tables = load_db_tables(config, source)?;
+        if tables.is_empty() {
+            Ok(DiffSource::GeneratedScripts(
+                Rpfm::load_generated_packfile_scripts(source, &config.layout.pack_script_root)?,
+            ))
+        } else {
+            Ok(DiffSource::DbTables(tables))
+        }
+    }
+}
+
+/// Loads all db tables from a packfile or a directory with extracted db files. Skipping any table is an error.
+fn load_db_tables(
+    config: &Config,
+    source: &Path,
+) -> Result<Vec<TotalWarDbPreProcessed>, Wh2LuaError> {
+    Log::clear_skipped();
+    let tables = Rpfm::load_source(config, source)?
+        .into_iter()
+        .flat_map(|(_, tables)| tables)
+        .collect();
+
+    let skipped_tables = Log::skipped_tables();
+    if !skipped_tables.is_empty() {
+        return Err(Wh2LuaError::TablesSkipped(skipped_tables));
+    }
+    Ok(tables)
+}
+
+/// Compares two sets of db tables, returning a diff for every table that has differences.
+///
+/// All db files of the same table are merged by row key. If several files contain the same key, the first one
+/// (in file name order) is used. Rows of tables without key columns cannot be matched up by key, so they are identified
+/// by their full contents instead: a changed row shows up as a removed row and an added row.
+pub fn diff_tables(
+    old_tables: &[TotalWarDbPreProcessed],
+    new_tables: &[TotalWarDbPreProcessed],
+) -> Vec<TableDiff> {
+    let old_rows = merge_rows_by_table(old_tables);
+    let new_rows = merge_rows_by_table(new_tables);
+
+    let table_names: BTreeSet<&String> = old_rows.keys().chain(new_rows.keys()).collect();
+    let empty = BTreeMap::new();
+
+    let mut result = Vec::new();
+    for table_name in table_names {
+        let old = old_rows.get(table_name).unwrap_or(&empty);
+        let new = new_rows.get(table_name).unwrap_or(&empty);
+
+        let mut table_diff = TableDiff {
+            table_name: table_name.clone(),
+            added: new
+                .keys()
+                .filter(|key| !old.contains_key(*key))
+                .cloned()
+                .collect(),
+            removed: old
+                .keys()
+                .filter(|key| !new.contains_key(*key))
+                .cloned()
+                .collect(),
+            changed: Vec::new(),
+        };
+
+        for (key, old_row) in old.iter() {
+            if let Some(new_row) = new.get(key) {
+                let field_changes = diff_rows(old_row, new_row);
+                if !field_changes.is_empty() {
+                    table_diff.changed.push((key.clone(), field_changes));
+                }
+            }
+        }
+
+        if !table_diff.is_empty() {
+            result.push(table_diff);
+        }
+    }
+
+    result
+}
+
+/// Compares two sets of generated script files by path and contents
+pub fn diff_scripts(
+    old_scripts: &BTreeMap<Vec<String>, String>,
+    new_scripts: &BTreeMap<Vec<String>, String>,
+) -> ScriptDiff {
+    ScriptDiff {
+        added: new_scripts
+            .keys()
+            .filter(|path| !old_scripts.contains_key(*path))
+            .map(|path| path.join("/"))
+            .collect(),
+        removed: old_scripts
+            .keys()
+            .filter(|path| !new_scripts.contains_key(*path))
+            .map(|path| path.join("/"))
+            .collect(),
+        changed: old_scripts
+            .iter()
+            .filter(|(path, contents)| {
+                new_scripts
+                    .get(*path)
+                    .map_or(false, |new_contents| new_contents != *contents)
+            })
+            .map(|(path, _)| path.join("/"))
+            .collect(),
+    }
+}
+
+/// table name -> row key -> row
+fn merge_rows_by_table(
+    tables: &[TotalWarDbPreProcessed],
+) -> BTreeMap<String, BTreeMap<String, &Vec<(LuaValue, LuaValue)>>> {
+    let mut sorted_tables: Vec<&TotalWarDbPreProcessed> = tables.iter().collect();
+    sorted_tables.sort_by(|a, b| a.script_file_path.cmp(&b.script_file_path));
+
+    let mut result: BTreeMap<String, BTreeMap<String, &Vec<(LuaValue, LuaValue)>>> =
+        BTreeMap::new();
+    for table in sorted_tables {
+        let rows = result
+            .entry(table.table_name.clone())
+            .or_insert_with(BTreeMap::new);
+        for (key, row) in table.keyed_rows() {
+            // keyed_rows uses the row number for these, which shifts with every inserted or removed row
+            let key = if table.key_columns.is_empty() {
+                row_contents_key(row)
+            } else {
+                key
+            };
+            rows.entry(key).or_insert(row);
+        }
+    }
+    result
+}
+
+/// Identifies a row of a table without key columns by all of its fields, e.g. "unit=my_unit|amount=2"
+fn row_contents_key(row: &[(LuaValue, LuaValue)]) -> String {
+    row.iter()
+        .map(|(k, v)| format!("{}={}", k.to_plain_string(), v.to_plain_string()))
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+fn diff_rows(
+    old_row: &[(LuaValue, LuaValue)],
+    new_row: &[(LuaValue, LuaValue)],
+) -> Vec<FieldChange> {
+    let old_fields: BTreeMap<String, String> = old_row
+        .iter()
+        .map(|(k, v)| (k.to_plain_string(), v.to_plain_string()))
+        .collect();
+    let new_fields: BTreeMap<String, String> = new_row
+        .iter()
+        .map(|(k, v)| (k.to_plain_string(), v.to_plain_string()))
+        .collect();
+
+    let field_names: BTreeSet<&String> = old_fields.keys().chain(new_fields.keys()).collect();
+
+    field_names
+        .into_iter()
+        .filter(|field| old_fields.get(*field) != new_fields.get(*field))
+        .map(|field| FieldChange {
+            field: field.clone(),
+            old_value: old_fields.get(field).cloned(),
+            new_value: new_fields.get(field).cloned(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tw_db_pp::TableData;
+
+    fn text(value: &str) -> LuaValue {
+        LuaValue::Text(value.to_string())
+    }
+
+    fn row(fields: &[(&str, &str)]) -> Vec<(LuaValue, LuaValue)> {
+        fields
+            .iter()
+            .map(|(field, value)| (text(field), text(value)))
+            .collect()
+    }
+
+    fn kv_table(rows: &[(&str, &str)]) -> TotalWarDbPreProcessed {
+        let data = rows
+            .iter()
+            .map(|(key, category)| (text(key), row(&[("key", key), ("category", category)])))
+            .collect();
+        TotalWarDbPreProcessed::new(
+            "units_tables",
+            TableData::KeyValue(data),
+            vec!["key".to_string()],
+            Some(1),
+            vec!["data__.lua".to_string()],
+        )
+    }
+
+    fn array_table(rows: &[(&str, &str)]) -> TotalWarDbPreProcessed {
+        let data = rows
+            .iter()
+            .map(|(unit, amount)| row(&[("unit", unit), ("amount", amount)]))
+            .collect();
+        TotalWarDbPreProcessed::new(
+            "units_to_groupings_tables",
+            TableData::FlatArray(data),
+            Vec::new(),
+            Some(1),
+            vec!["data__.lua".to_string()],
+        )
+    }
+
+    #[test]
+    fn diffs_tables_by_key() {
+        let old = kv_table(&[("a", "inf"), ("b", "cav"), ("c", "art")]);
+        let new = kv_table(&[("a", "inf"), ("b", "inf"), ("d", "art")]);
+
+        let diffs = diff_tables(&[old], &[new]);
+        assert_eq!(diffs.len(), 1);
+        let diff = &diffs[0];
+        assert_eq!(diff.table_name, "units_tables");
+        assert_eq!(diff.added, vec!["d".to_string()]);
+        assert_eq!(diff.removed, vec!["c".to_string()]);
+        assert_eq!(diff.changed.len(), 1);
+        let (key, field_changes) = &diff.changed[0];
+        assert_eq!(key, "b");
+        assert_eq!(field_changes.len(), 1);
+        assert_eq!(field_changes[0].field, "category");
+        assert_eq!(field_changes[0].old_value.as_deref(), Some("cav"));
+        assert_eq!(field_changes[0].new_value.as_deref(), Some("inf"));
+    }
+
+    #[test]
+    fn reports_no_diff_for_equal_tables() {
+        let old = kv_table(&[("a", "inf")]);
+        let new = kv_table(&[("a", "inf")]);
+        assert!(diff_tables(&[old], &[new]).is_empty());
+    }
+
+    #[test]
+    fn inserted_row_in_table_without_keys_only_reports_that_row() {
+        let old = array_table(&[("u1", "1"), ("u2", "2")]);
+        let new = array_table(&[("u0", "5"), ("u1", "1"), ("u2", "2")]);
+
+        let diffs = diff_tables(&[old], &[new]);
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(diffs[0].added, vec!["unit=u0|amount=5".to_string()]);
+        assert!(diffs[0].removed.is_empty());
+        assert!(diffs[0].changed.is_empty());
+    }
+}
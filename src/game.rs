@@ -0,0 +1,68 @@
+use rpfm_lib::packfile::PFHVersion;
+
+use crate::wh2_lua_error::Wh2LuaError;
+
+/// The game used when no game is selected
+pub const DEFAULT_GAME: &str = "warhammer_2";
+
+/// Everything that differs between the supported Total War games
+#[derive(Debug)]
+pub struct GameProfile {
+    /// The name used to select the game (with --game), which is also its name in RPFM (used to select the schema)
+    pub name: &'static str,
+    pub display_name: &'static str,
+    /// The game executables in the install directory, in order of preference. The *_real.exe variant is the actual
+    /// game when started through a launcher that takes the place of the game executable.
+    pub executables: &'static [&'static str],
+    /// The folder with this game's profiles, in KMM's Profiles folder
+    pub kmm_profiles_folder: &'static str,
+    /// The Steam app id, which is also the name of the game's workshop content folder
+    pub steam_app_id: &'static str,
+    /// The packfile format version used by the game
+    pub pack_version: PFHVersion,
+}
+
+pub static GAME_PROFILES: &[GameProfile] = &[
+    GameProfile {
+        name: "warhammer_2",
+        display_name: "Total War: Warhammer II",
+        executables: &["Warhammer2_real.exe", "Warhammer2.exe"],
+        kmm_profiles_folder: "Warhammer2",
+        steam_app_id: "594570",
+        pack_version: PFHVersion::PFH5,
+    },
+    GameProfile {
+        name: "three_kingdoms",
+        display_name: "Total War: Three Kingdoms",
+        executables: &["Three_Kingdoms_real.exe", "Three_Kingdoms.exe"],
+        kmm_profiles_folder: "ThreeKingdoms",
+        steam_app_id: "779340",
+        pack_version: PFHVersion::PFH5,
+    },
+    GameProfile {
+        name: "troy",
+        display_name: "A Total War Saga: Troy",
+        executables: &["Troy_real.exe", "Troy.exe"],
+        kmm_profiles_folder: "Troy",
+        steam_app_id: "1099410",
+        pack_version: PFHVersion::PFH6,
+    },
+];
+
+/// Returns the profile of the game with the given name
+pub fn find_game_profile(name: &str) -> Result<&'static GameProfile, Wh2LuaError> {
+    GAME_PROFILES
+        .iter()
+        .find(|game| game.name == name)
+        .ok_or_else(|| {
+            Wh2LuaError::ConfigError(format!(
+                "Unknown game: {} (supported games: {})",
+                name,
+                GAME_PROFILES
+                    .iter()
+                    .map(|game| game.name)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))
+        })
+}
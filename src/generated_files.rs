@@ -0,0 +1,179 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::config::{Config, OutputSinkKind, OverwritePolicy};
+use crate::log::Log;
+use crate::wh2_lua_error::Wh2LuaError;
+
+/// The file in the output directory listing all files generated there, one path (relative to the output directory,
+/// with "/" separators) per line
+pub const GENERATED_FILES_LIST: &str = "lua_db_generated_files.txt";
+
+/// Reads the list of files generated in the output directory by previous runs. Empty if there is no list.
+pub fn read_generated_files(out_dir: &Path) -> Result<BTreeSet<String>, Wh2LuaError> {
+    let list_path = out_dir.join(GENERATED_FILES_LIST);
+    if !list_path.exists() {
+        return Ok(BTreeSet::new());
+    }
+
+    Ok(fs::read_to_string(list_path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Updates the list of generated files in the output directory after the given files have been written (unpacked
+/// output only).
+///
+/// With the Clean overwrite policy, files from the list that were not generated this time are removed from the output
+/// directory. Otherwise, they are left alone, and stay on the list.
+pub fn update_generated_files<'a>(
+    config: &Config,
+    generated: impl IntoIterator<Item = &'a Vec<String>>,
+) -> Result<(), Wh2LuaError> {
+    if config.sink != OutputSinkKind::Directory {
+        return Ok(());
+    }
+
+    let generated: BTreeSet<String> = generated.into_iter().map(|path| path.join("/")).collect();
+    update_generated_files_in(
+        &config.out_dir,
+        config.overwrite == OverwritePolicy::Clean,
+        generated,
+    )
+}
+
+/// Updates the list of generated files in the given output directory, removing stale files if `clean` is set (see
+/// `update_generated_files`)
+fn update_generated_files_in(
+    out_dir: &Path,
+    clean: bool,
+    generated: BTreeSet<String>,
+) -> Result<(), Wh2LuaError> {
+    let previous = read_generated_files(out_dir)?;
+
+    let tracked = if clean {
+        for stale_path in previous.difference(&generated) {
+            // Never remove anything outside of the output directory
+            if !is_relative_path(stale_path) {
+                continue;
+            }
+            let file_path = relative_path_to_file_path(out_dir, stale_path);
+            if file_path.is_file() {
+                Log::info(&format!("Removing stale file: {}", file_path.display()));
+                fs::remove_file(&file_path)?;
+                remove_empty_parent_dirs(out_dir, &file_path)?;
+            }
+        }
+        generated
+    } else {
+        previous.union(&generated).cloned().collect()
+    };
+
+    let mut contents = tracked.into_iter().collect::<Vec<_>>().join("\n");
+    contents.push('\n');
+    fs::write(out_dir.join(GENERATED_FILES_LIST), contents)?;
+    Ok(())
+}
+
+/// Returns true if the path (with "/" separators) only consists of plain file and folder names, i.e. it cannot point
+/// outside of the directory it is relative to (no "..", root or drive components)
+fn is_relative_path(relative_path: &str) -> bool {
+    relative_path.split('/').all(|e| {
+        let mut components = Path::new(e).components();
+        matches!(components.next(), Some(Component::Normal(_))) && components.next().is_none()
+    })
+}
+
+fn relative_path_to_file_path(out_dir: &Path, relative_path: &str) -> PathBuf {
+    let mut file_path = out_dir.to_path_buf();
+    relative_path.split('/').for_each(|e| file_path.push(e));
+    file_path
+}
+
+/// Removes the directories between the output directory and the removed file, as long as they are empty
+fn remove_empty_parent_dirs(out_dir: &Path, file_path: &Path) -> Result<(), Wh2LuaError> {
+    let mut dir = file_path.parent();
+    while let Some(current_dir) = dir {
+        if current_dir == out_dir || current_dir.read_dir()?.next().is_some() {
+            break;
+        }
+        fs::remove_dir(current_dir)?;
+        dir = current_dir.parent();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{write_file, TestDir};
+
+    fn paths(paths: &[&str]) -> BTreeSet<String> {
+        paths.iter().map(|path| path.to_string()).collect()
+    }
+
+    #[test]
+    fn clean_removes_only_stale_listed_files() {
+        let dir = TestDir::new("generated_files_clean");
+        let out_dir = dir.path().join("out");
+        write_file(&out_dir.join("lua_db/mod/a/kept.lua"), "");
+        write_file(&out_dir.join("lua_db/mod/b/stale.lua"), "");
+        write_file(&out_dir.join("lua_db/mod/b/not_listed.lua"), "");
+        write_file(&out_dir.join("lua_db/mod/c/stale.lua"), "");
+        fs::write(
+            out_dir.join(GENERATED_FILES_LIST),
+            "lua_db/mod/a/kept.lua\nlua_db/mod/b/stale.lua\nlua_db/mod/c/stale.lua\n",
+        )
+        .unwrap();
+
+        update_generated_files_in(&out_dir, true, paths(&["lua_db/mod/a/kept.lua"])).unwrap();
+
+        assert!(out_dir.join("lua_db/mod/a/kept.lua").is_file());
+        assert!(!out_dir.join("lua_db/mod/b/stale.lua").exists());
+        assert!(out_dir.join("lua_db/mod/b/not_listed.lua").is_file());
+        // Emptied folders are removed as well
+        assert!(!out_dir.join("lua_db/mod/c").exists());
+        assert_eq!(
+            read_generated_files(&out_dir).unwrap(),
+            paths(&["lua_db/mod/a/kept.lua"])
+        );
+    }
+
+    #[test]
+    fn clean_never_removes_files_outside_of_output_dir() {
+        let dir = TestDir::new("generated_files_outside");
+        let out_dir = dir.path().join("out");
+        let outside_file = dir.path().join("outside.lua");
+        write_file(&outside_file, "");
+        fs::create_dir_all(&out_dir).unwrap();
+        fs::write(
+            out_dir.join(GENERATED_FILES_LIST),
+            format!("../outside.lua\n{}\n", outside_file.display()),
+        )
+        .unwrap();
+
+        update_generated_files_in(&out_dir, true, BTreeSet::new()).unwrap();
+
+        assert!(outside_file.is_file());
+    }
+
+    #[test]
+    fn merge_keeps_stale_files_listed() {
+        let dir = TestDir::new("generated_files_merge");
+        let out_dir = dir.path();
+        write_file(&out_dir.join("lua_db/old.lua"), "");
+        fs::write(out_dir.join(GENERATED_FILES_LIST), "lua_db/old.lua\n").unwrap();
+
+        update_generated_files_in(out_dir, false, paths(&["lua_db/new.lua"])).unwrap();
+
+        assert!(out_dir.join("lua_db/old.lua").is_file());
+        assert_eq!(
+            read_generated_files(out_dir).unwrap(),
+            paths(&["lua_db/new.lua", "lua_db/old.lua"])
+        );
+    }
+}
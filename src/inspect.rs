@@ -0,0 +1,221 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use clap::ArgMatches;
+
+use serde_json::{Map, Value};
+
+use wh2_luadb::config::Config;
+use wh2_luadb::log::Log;
+use wh2_luadb::rpfm::Rpfm;
+use wh2_luadb::tw_db_pp::{LuaValue, TableData, TotalWarDbPreProcessed};
+use wh2_luadb::Wh2LuaError;
+
+use crate::cli::ArgsParser;
+use crate::commands::CommandResult;
+
+/// Decodes a single db table from a packfile or an extracted db file, and prints it as text, JSON or Lua.
+pub fn inspect(matches: &ArgMatches) -> CommandResult {
+    let source = match (matches.value_of("packfile"), matches.value_of("file")) {
+        (Some(packfile), _) => PathBuf::from(packfile),
+        (None, Some(file)) => PathBuf::from(file),
+        (None, None) => {
+            return Err(Wh2LuaError::ConfigError(
+                "Either a packfile or an extracted db file must be specified".to_string(),
+            ))
+        }
+    };
+
+    let schema = Rpfm::load_schema(ArgsParser::parse_game_arg(matches)?.name)?;
+    let config = Config::new(schema, PathBuf::from("."));
+
+    let key = matches.value_of("key");
+    let columns: Vec<&str> = matches
+        .values_of("column")
+        .map(|values| values.collect())
+        .unwrap_or_default();
+
+    let tables: Vec<TotalWarDbPreProcessed> =
+        Rpfm::load_single_table(&config, &source, matches.value_of("table"))?
+            .iter()
+            .map(|table| filter_table(table, key, &columns))
+            .collect();
+
+    match matches.value_of("format").unwrap_or("text") {
+        "text" => {
+            for table in &tables {
+                println!("{}", table.script_file_path.join("/"));
+                println!("{}", render_text_table(table, &columns));
+            }
+        }
+        "json" => {
+            let mut json = Map::new();
+            for table in &tables {
+                json.insert(table.script_file_path.join("/"), json_table(table));
+            }
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&Value::Object(json)).unwrap()
+            );
+        }
+        "lua" => {
+            for table in &tables {
+                println!("-- {}", table.script_file_path.join("/"));
+                println!("{}", wh2_luadb::render_table(&config, table)?);
+            }
+        }
+        other => {
+            return Err(Wh2LuaError::ConfigError(format!(
+                "Unknown inspect format: {} (expected text, json or lua)",
+                other
+            )))
+        }
+    }
+
+    let row_count: usize = tables.iter().map(|table| table.keyed_rows().len()).sum();
+    Log::info(&format!("{} row(s) shown", row_count));
+
+    Ok(None)
+}
+
+/// Returns a copy of the table with only the rows matching the key (if given) and only the given columns (if any).
+fn filter_table(
+    table: &TotalWarDbPreProcessed,
+    key: Option<&str>,
+    columns: &[&str],
+) -> TotalWarDbPreProcessed {
+    let filter_row = |row: &Vec<(LuaValue, LuaValue)>| -> Vec<(LuaValue, LuaValue)> {
+        row.iter()
+            .filter(|(k, _)| columns.is_empty() || columns.contains(&k.to_plain_string().as_str()))
+            .cloned()
+            .collect()
+    };
+
+    let matching_keys: Vec<String> = table
+        .keyed_rows()
+        .into_iter()
+        .map(|(row_key, _)| row_key)
+        .collect();
+    let matches_key = |row_key: &str| key.map_or(true, |key| key == row_key);
+
+    let data = match &table.data {
+        TableData::KeyValue(kv_table_data) => TableData::KeyValue(
+            kv_table_data
+                .iter()
+                .filter(|(k, _)| matches_key(&k.to_plain_string()))
+                .map(|(k, row)| (k.clone(), filter_row(row)))
+                .collect::<BTreeMap<_, _>>(),
+        ),
+        TableData::FlatArray(arr_table_data) => TableData::FlatArray(
+            arr_table_data
+                .iter()
+                .zip(matching_keys.iter())
+                .filter(|(_, row_key)| matches_key(row_key))
+                .map(|(row, _)| filter_row(row))
+                .collect(),
+        ),
+    };
+
+    TotalWarDbPreProcessed::new(
+        &table.table_name,
+        data,
+        table.key_columns.clone(),
+        table.version,
+        table.script_file_path.clone(),
+    )
+}
+
+/// Renders the table as an aligned text table, with a header line
+fn render_text_table(table: &TotalWarDbPreProcessed, columns: &[&str]) -> String {
+    let rows = table.keyed_rows();
+
+    let column_names: Vec<String> = if !columns.is_empty() {
+        columns.iter().map(|column| column.to_string()).collect()
+    } else if let Some((_, first_row)) = rows.first() {
+        first_row.iter().map(|(k, _)| k.to_plain_string()).collect()
+    } else {
+        Vec::new()
+    };
+
+    let cells: Vec<Vec<String>> = rows
+        .iter()
+        .map(|(_, row)| {
+            column_names
+                .iter()
+                .map(|column_name| {
+                    row.iter()
+                        .find(|(k, _)| &k.to_plain_string() == column_name)
+                        .map(|(_, v)| v.to_plain_string())
+                        .unwrap_or_default()
+                })
+                .collect()
+        })
+        .collect();
+
+    let widths: Vec<usize> = column_names
+        .iter()
+        .enumerate()
+        .map(|(index, column_name)| {
+            cells
+                .iter()
+                .map(|row| row[index].chars().count())
+                .chain(std::iter::once(column_name.chars().count()))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    let render_line = |values: &[String]| -> String {
+        values
+            .iter()
+            .zip(widths.iter())
+            .map(|(value, width)| format!("{:width$}", value, width = *width))
+            .collect::<Vec<_>>()
+            .join(" | ")
+            .trim_end()
+            .to_string()
+    };
+
+    let mut result = render_line(&column_names);
+    result.push('\n');
+    result.push_str(
+        &widths
+            .iter()
+            .map(|width| "-".repeat(*width))
+            .collect::<Vec<_>>()
+            .join("-+-"),
+    );
+    for row in &cells {
+        result.push('\n');
+        result.push_str(&render_line(row));
+    }
+    result
+}
+
+/// Converts the table to a JSON array of row objects
+fn json_table(table: &TotalWarDbPreProcessed) -> Value {
+    Value::Array(
+        table
+            .keyed_rows()
+            .into_iter()
+            .map(|(_, row)| {
+                let mut json_row = Map::new();
+                for (k, v) in row {
+                    json_row.insert(k.to_plain_string(), json_value(v));
+                }
+                Value::Object(json_row)
+            })
+            .collect(),
+    )
+}
+
+fn json_value(value: &LuaValue) -> Value {
+    match value {
+        LuaValue::Boolean(value) => Value::Bool(*value),
+        // Numbers that are not valid JSON (e.g. NaN) are kept as strings
+        LuaValue::Number(value) => {
+            serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.clone()))
+        }
+        LuaValue::Text(value) => Value::String(value.clone()),
+    }
+}
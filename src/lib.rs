@@ -0,0 +1,205 @@
+//! Generates Lua data tables from Total War: Warhammer 2 DB files.
+//!
+//! The pipeline has three steps, each of which can be used on its own:
+//! 1. [`load`] decodes the db tables from the packfiles or extracted files listed in the [`Config`],
+//! 2. [`render_table`] turns a single pre-processed table into a script in the configured output format
+//!    (see [`output_writer::OutputWriter`]),
+//! 3. [`generate`] runs the whole pipeline and writes the scripts to the configured destination
+//!    (see [`output_sink::OutputSink`]).
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+pub mod config;
+pub mod diff;
+pub mod game;
+mod generated_files;
+pub mod log;
+pub mod lua_writer;
+mod manifest;
+pub mod output_index;
+pub mod output_manifest;
+pub mod output_sink;
+pub mod output_stubs;
+pub mod output_summary;
+pub mod output_writer;
+pub mod reverse_index;
+pub mod rpfm;
+pub mod steam;
+#[cfg(test)]
+mod test_support;
+pub mod tw_db_pp;
+mod util;
+pub mod watch;
+pub mod wh2_lua_error;
+
+pub use crate::config::{
+    Config, EmptyOptionalPolicy, MissingPackfilePolicy, OutputLayout, OutputSinkKind,
+    OverwritePolicy, PackSettings, ScriptFolder,
+};
+pub use crate::game::{find_game_profile, GameProfile};
+pub use crate::lua_writer::LuaWriter;
+pub use crate::output_index::OutputIndex;
+pub use crate::output_manifest::OutputManifest;
+pub use crate::output_sink::{create_output_sink, restore_backup, OutputSink};
+pub use crate::output_stubs::OutputStubs;
+pub use crate::output_summary::OutputSummary;
+pub use crate::output_writer::{find_output_writer, OutputWriter};
+pub use crate::reverse_index::ReverseIndex;
+pub use crate::rpfm::{PreProcessedSources, Rpfm};
+pub use crate::tw_db_pp::{Column, LuaValue, TableData, TotalWarDbPreProcessed};
+pub use crate::wh2_lua_error::Wh2LuaError;
+
+use crate::log::Log;
+
+/// Loads and pre-processes all db tables from the sources in the config.
+/// Returns the pre-processed tables per source (packfile name or input directory name), in load order.
+pub fn load(config: &Config) -> Result<PreProcessedSources, Wh2LuaError> {
+    Rpfm::load(config)
+}
+
+/// Renders a single pre-processed table in the output format selected in the config.
+pub fn render_table(
+    config: &Config,
+    table: &TotalWarDbPreProcessed,
+) -> Result<String, Wh2LuaError> {
+    find_output_writer(&config.format)?.render_table(config, table)
+}
+
+/// Runs the whole pipeline: prepares the output directory, loads all sources, renders every table
+/// and writes the resulting scripts to the configured output sink.
+pub fn generate(config: &Config) -> Result<(), Wh2LuaError> {
+    let writer = find_output_writer(&config.format)?;
+
+    prepare_output_dir(config)?;
+
+    let preprocessed_packfiles = load(config)?;
+
+    let mut sink = create_output_sink(config);
+
+    // script path -> source packfile name, to detect scripts overwritten by later packfiles
+    let mut generated_scripts: HashMap<Vec<String>, String> = HashMap::new();
+    let mut support_file_owners = SupportFileOwners::new();
+    let mut summary = OutputSummary::new(config);
+
+    // In load order, so scripts from later packfiles overwrite those from earlier ones
+    for (packfile_name, tables) in preprocessed_packfiles.iter() {
+        #[cfg(not(debug_assertions))]
+        Log::set_single_line_log(true);
+
+        for table in tables {
+            let file_name = table.script_file_path.last().unwrap();
+            Log::info(&format!(
+                "Generating {} script for {} - {}/{}",
+                writer.name(),
+                packfile_name,
+                &table.table_name,
+                // Drop file extension
+                Path::new(file_name).file_stem().unwrap().to_string_lossy()
+            ));
+            let files = render_table_files(config, writer.as_ref(), table)?;
+            claim_support_files(&mut support_file_owners, table, &files)?;
+
+            if let Some(overwritten) =
+                generated_scripts.insert(table.script_file_path.clone(), packfile_name.clone())
+            {
+                Log::set_single_line_log(false);
+                Log::warning(&format!(
+                    "Packfile {} overwrites script {} from packfile {}",
+                    packfile_name,
+                    &table.script_file_path.join("/"),
+                    overwritten
+                ));
+                #[cfg(not(debug_assertions))]
+                Log::set_single_line_log(true);
+            } else if sink.exists(&table.script_file_path) {
+                Log::add_overwritten_file(sink.display_path(&table.script_file_path));
+            }
+
+            summary.add_table(packfile_name, table, &files);
+            for (path, contents) in files.iter() {
+                generated_scripts.insert(path.clone(), packfile_name.clone());
+                sink.write_file(path, contents)?;
+            }
+        }
+
+        Log::info(&format!(
+            "Generating {} scripts for {} - DONE",
+            writer.name(),
+            packfile_name
+        ));
+        Log::set_single_line_log(false);
+    }
+
+    // The manifest, index and type stubs
+    let extra_files = summary.files(config);
+    for (path, contents) in extra_files.iter() {
+        sink.write_file(path, contents)?;
+    }
+
+    sink.finish()?;
+
+    generated_files::update_generated_files(
+        config,
+        generated_scripts
+            .keys()
+            .chain(extra_files.iter().map(|(path, _)| path)),
+    )?;
+
+    Ok(())
+}
+
+/// Renders all files for a single table: the script itself (first), followed by any other files generated for it (chunks
+/// of a large table, and the configured reverse indexes)
+pub(crate) fn render_table_files(
+    config: &Config,
+    writer: &dyn OutputWriter,
+    table: &TotalWarDbPreProcessed,
+) -> Result<Vec<(Vec<String>, String)>, Wh2LuaError> {
+    let mut files = writer.render_table_files(config, table)?;
+    files.extend(ReverseIndex::render_all(config, table));
+    Ok(files)
+}
+
+/// Support file path (see `OutputLayout::support_file_path`) -> path of the script it was generated for
+pub(crate) type SupportFileOwners = HashMap<Vec<String>, Vec<String>>;
+
+/// Registers the support files of a table (all files rendered for it, except the script itself). Returns an error if
+/// one of them was already generated for another script: dots are replaced in support file paths, so e.g. the scripts
+/// "my.mod_data__" and "my_mod_data__" would otherwise overwrite each other's chunks and reverse indexes.
+pub(crate) fn claim_support_files(
+    owners: &mut SupportFileOwners,
+    table: &TotalWarDbPreProcessed,
+    files: &[(Vec<String>, String)],
+) -> Result<(), Wh2LuaError> {
+    for (path, _) in files.iter().skip(1) {
+        if let Some(owner) = owners.insert(path.clone(), table.script_file_path.clone()) {
+            if owner != table.script_file_path {
+                return Err(Wh2LuaError::ConfigError(format!(
+                    "The scripts {} and {} would both generate {}.\n  Use a --file-template or --core-prefix that gives them distinct names (ignoring dots).",
+                    owner.join("/"),
+                    table.script_file_path.join("/"),
+                    path.join("/")
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Creates the output directory if it doesn ot exists. Returns an error if the output dir is not empty (and the overwrite policy is Fail)
+pub(crate) fn prepare_output_dir(config: &Config) -> Result<(), Wh2LuaError> {
+    if config.sink == OutputSinkKind::Stdout {
+        return Ok(());
+    }
+    fs::create_dir_all(&config.out_dir)?;
+    // Directory is empty if its iterator has no elements
+    if config.sink == OutputSinkKind::Directory
+        && config.overwrite == OverwritePolicy::Fail
+        && !&config.out_dir.read_dir()?.next().is_none()
+    {
+        return Err(Wh2LuaError::OutDirNotEmpty(config.out_dir.clone()));
+    }
+    Ok(())
+}
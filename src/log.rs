@@ -8,6 +8,8 @@ use std::io::stderr;
 
 static mut SINGLE_LINE: bool = false;
 static mut FILES_OVERWRITTEN: Vec<String> = Vec::new();
+static mut PACKFILES_SKIPPED: Vec<String> = Vec::new();
+static mut TABLES_SKIPPED: Vec<String> = Vec::new();
 
 /// Provides static functions to log things to console (through stderr)
 pub struct Log {}
@@ -88,4 +90,60 @@ impl Log {
             }
         }
     }
+
+    /// Add a description of a skipped packfile to the (static) list of skipped packfiles
+    pub fn add_skipped_packfile(packfile_description: String) {
+        unsafe {
+            PACKFILES_SKIPPED.push(packfile_description);
+        }
+    }
+
+    /// Logs all packfiles in the static list of skipped packfiles
+    pub fn print_skipped_packfiles() {
+        unsafe {
+            if !&PACKFILES_SKIPPED.is_empty() {
+                Self::warning("packfiles skipped: ");
+                for packfile in &PACKFILES_SKIPPED {
+                    eprintln!("{}", packfile);
+                }
+            }
+        }
+    }
+
+    /// Add a description of a skipped db table to the (static) list of skipped tables
+    pub fn add_skipped_table(table_description: String) {
+        unsafe {
+            TABLES_SKIPPED.push(table_description);
+        }
+    }
+
+    /// Clears the static lists of skipped packfiles and tables, e.g. before a file is processed again in watch mode
+    pub fn clear_skipped() {
+        unsafe {
+            PACKFILES_SKIPPED.clear();
+            TABLES_SKIPPED.clear();
+        }
+    }
+
+    /// Returns a copy of the static list of skipped tables
+    pub fn skipped_tables() -> Vec<String> {
+        unsafe { TABLES_SKIPPED.clone() }
+    }
+
+    /// Logs all tables in the static list of skipped tables
+    pub fn print_skipped_tables() {
+        unsafe {
+            if !&TABLES_SKIPPED.is_empty() {
+                Self::warning("tables skipped: ");
+                for table in &TABLES_SKIPPED {
+                    eprintln!("{}", table);
+                }
+            }
+        }
+    }
+
+    /// Returns the number of packfiles and tables skipped so far
+    pub fn skipped_count() -> usize {
+        unsafe { PACKFILES_SKIPPED.len() + TABLES_SKIPPED.len() }
+    }
 }
@@ -1,95 +1,576 @@
 use crate::config::Config;
+use crate::output_writer::OutputWriter;
 use crate::tw_db_pp::{LuaValue, TableData, TotalWarDbPreProcessed};
 use crate::wh2_lua_error::Wh2LuaError;
 
-use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// The Lua 5.1 VM used by the game allows at most 200 local variables per function. Interned strings beyond this
+/// number go into a lookup table instead, leaving room for the locals the script itself needs.
+const MAX_INTERNED_LOCALS: usize = 180;
+
+/// The folder in the root folder that contains the chunks of all split scripts (see `OutputLayout::support_file_path`)
+const CHUNK_FOLDER: &str = "_chunks";
+
+/// A row of a table, with its key (for tables generated with a single key column)
+type Row<'a> = (Option<&'a LuaValue>, &'a Vec<(LuaValue, LuaValue)>);
 
 pub struct LuaWriter {}
 
+impl OutputWriter for LuaWriter {
+    fn name(&self) -> &'static str {
+        "lua"
+    }
+
+    fn file_extension(&self) -> &'static str {
+        "lua"
+    }
+
+    fn render_table(
+        &self,
+        config: &Config,
+        table: &TotalWarDbPreProcessed,
+    ) -> Result<String, Wh2LuaError> {
+        Self::convert_tw_db_to_lua_script(config, table)
+    }
+
+    fn render_table_files(
+        &self,
+        config: &Config,
+        table: &TotalWarDbPreProcessed,
+    ) -> Result<Vec<(Vec<String>, String)>, Wh2LuaError> {
+        let unchunked = || -> Result<Vec<(Vec<String>, String)>, Wh2LuaError> {
+            Ok(vec![(
+                table.script_file_path.clone(),
+                Self::convert_tw_db_to_lua_script(config, table)?,
+            )])
+        };
+        if config.chunk_rows.is_none() && config.chunk_bytes.is_none() {
+            return unchunked();
+        }
+
+        let rows = Self::table_rows(&table.data);
+        let lua_format = LuaFormat::new(config, &rows);
+        let chunks = Self::chunk_rows(config, &Self::lua_rows(&lua_format, &rows, 1)?);
+        if chunks.len() <= 1 {
+            return unchunked();
+        }
+
+        let chunk_paths: Vec<Vec<String>> = (1..=chunks.len())
+            .map(|chunk_number| Self::chunk_path(config, table, chunk_number))
+            .collect();
+
+        let mut files = vec![(
+            table.script_file_path.clone(),
+            Self::stitcher_script(config, table, &chunk_paths),
+        )];
+        for (chunk_path, chunk) in chunk_paths.into_iter().zip(chunks.into_iter()) {
+            // Each chunk is a separate script, with its own interned strings
+            let chunk_rows = &rows[chunk];
+            let chunk_format = LuaFormat::new(config, chunk_rows);
+            files.push((
+                chunk_path,
+                format!(
+                    "{}return {{{}{}}}",
+                    chunk_format.declarations(),
+                    chunk_format.newline(),
+                    Self::lua_rows(&chunk_format, chunk_rows, 1)?.concat()
+                ),
+            ));
+        }
+        Ok(files)
+    }
+}
+
 impl LuaWriter {
     pub fn convert_tw_db_to_lua_script(
         config: &Config,
         table_data: &TotalWarDbPreProcessed,
     ) -> Result<String, Wh2LuaError> {
-        let mut result = String::new();
+        let rows = Self::table_rows(&table_data.data);
+        let lua_format = LuaFormat::new(config, &rows);
+        let mut result = lua_format.declarations();
         let mut indent: usize = 0;
 
         if let Some(script_check) = &config.script_check {
-            result.push_str("local result = nil\n\n");
+            result.push_str(&format!(
+                "local result{}nil\n{}",
+                lua_format.assign(),
+                lua_format.newline()
+            ));
             result.push_str(&format!("if vfs.exists(\"{}\") then\n", script_check));
             indent += 1;
-            result.push_str(&format!("{}result = {{\n", "  ".repeat(indent)));
+            result.push_str(&format!(
+                "{}result{}{{{}",
+                lua_format.indent(indent),
+                lua_format.assign(),
+                lua_format.newline()
+            ));
         } else {
-            result.push_str(&format!("{}local result = {{\n", "  ".repeat(indent)));
+            result.push_str(&format!(
+                "{}local result{}{{{}",
+                lua_format.indent(indent),
+                lua_format.assign(),
+                lua_format.newline()
+            ));
         }
 
         indent += 1;
 
-        match &table_data.data {
-            TableData::KeyValue(kv_table_data) => {
-                result.push_str(&Self::lua_key_value_table(&kv_table_data, indent)?);
-            }
-            TableData::FlatArray(arr_table_data) => {
-                result.push_str(&Self::lua_array_table(&arr_table_data, indent)?);
-            }
-        }
+        result.push_str(&Self::lua_rows(&lua_format, &rows, indent)?.concat());
 
         indent -= 1;
 
-        result.push_str(&format!("{}}}\n", "  ".repeat(indent)));
+        result.push_str(&format!("{}}}\n", lua_format.indent(indent)));
 
         if config.script_check.is_some() {
             indent -= 1;
-            result.push_str(&format!("{}end\n", "  ".repeat(indent)));
+            result.push_str(&format!("{}end\n", lua_format.indent(indent)));
         }
 
         while indent > 1 {
             indent -= 1;
-            result.push_str(&format!("{}}}\n", "  ".repeat(indent)));
+            result.push_str(&format!("{}}}\n", lua_format.indent(indent)));
         }
 
-        result.push_str("\nreturn result");
+        result.push_str(&format!("{}return result", lua_format.newline()));
 
         Ok(result)
     }
 
-    fn lua_key_value_table(
-        kv_table_data: &BTreeMap<LuaValue, Vec<(LuaValue, LuaValue)>>,
-        indent: usize,
-    ) -> Result<String, Wh2LuaError> {
+    /// Splits the rendered rows into chunks of at most `Config::chunk_rows` rows and `Config::chunk_bytes` bytes
+    /// (but at least one row each), and returns the row ranges of the chunks. Returns a single chunk if no threshold is
+    /// set, or the table is small enough.
+    fn chunk_rows(config: &Config, rows: &[String]) -> Vec<Range<usize>> {
+        let max_rows = config.chunk_rows.unwrap_or(usize::MAX);
+        let max_bytes = config.chunk_bytes.unwrap_or(usize::MAX);
+
+        let mut chunks = Vec::new();
+        let mut chunk_start = 0;
+        let mut chunk_bytes = 0;
+        for (index, row) in rows.iter().enumerate() {
+            let chunk_rows = index - chunk_start;
+            if chunk_rows > 0 && (chunk_rows >= max_rows || chunk_bytes + row.len() > max_bytes) {
+                chunks.push(chunk_start..index);
+                chunk_start = index;
+                chunk_bytes = 0;
+            }
+            chunk_bytes += row.len();
+        }
+        chunks.push(chunk_start..rows.len());
+        chunks
+    }
+
+    /// Path of a chunk of a table's script: the path of the script in the chunk folder, with "__chunk_<number>"
+    /// appended to the file name (e.g. lua_db/_chunks/core/land_units_tables/data____chunk_1.lua)
+    fn chunk_path(
+        config: &Config,
+        table: &TotalWarDbPreProcessed,
+        chunk_number: usize,
+    ) -> Vec<String> {
+        config.layout.support_file_path(
+            CHUNK_FOLDER,
+            &table.script_file_path,
+            &format!("__chunk_{}", chunk_number),
+        )
+    }
+
+    /// The script that requires all chunks of a table, and returns the same table as an unchunked script would
+    fn stitcher_script(
+        config: &Config,
+        table_data: &TotalWarDbPreProcessed,
+        chunk_paths: &[Vec<String>],
+    ) -> String {
         let mut result = String::new();
+        let mut indent: usize = 0;
+
+        if let Some(script_check) = &config.script_check {
+            result.push_str("local result = nil\n\n");
+            result.push_str(&format!("if vfs.exists(\"{}\") then\n", script_check));
+            indent += 1;
+            result.push_str(&format!("{}result = {{}}\n", "  ".repeat(indent)));
+        } else {
+            result.push_str("local result = {}\n");
+        }
 
-        for (key, values) in kv_table_data.iter() {
+        let pad = "  ".repeat(indent);
+        result.push_str(&format!("{}for _, chunk in ipairs({{\n", pad));
+        for chunk_path in chunk_paths {
+            // Required without extension
+            let require_path = chunk_path.join("/");
+            let require_path = require_path.trim_end_matches(".lua");
             result.push_str(&format!(
-                "{}[{}] = {{ ",
-                "  ".repeat(indent),
-                key.to_lua_value()
+                "{}  {},\n",
+                pad,
+                LuaValue::Text(require_path.to_string()).to_lua_value()
             ));
+        }
+        result.push_str(&format!("{}}}) do\n", pad));
+        match &table_data.data {
+            TableData::KeyValue(_) => {
+                result.push_str(&format!(
+                    "{}  for key, row in pairs(require(chunk)) do\n",
+                    pad
+                ));
+                result.push_str(&format!("{}    result[key] = row\n", pad));
+            }
+            TableData::FlatArray(_) => {
+                result.push_str(&format!(
+                    "{}  for _, row in ipairs(require(chunk)) do\n",
+                    pad
+                ));
+                result.push_str(&format!("{}    result[#result + 1] = row\n", pad));
+            }
+        }
+        result.push_str(&format!("{}  end\n", pad));
+        result.push_str(&format!("{}end\n", pad));
+
+        if config.script_check.is_some() {
+            result.push_str("end\n");
+        }
+
+        result.push_str("\nreturn result");
+
+        result
+    }
+
+    /// Returns the rows of the table, in the order they are generated in
+    fn table_rows(data: &TableData) -> Vec<Row> {
+        match data {
+            TableData::KeyValue(kv_table_data) => kv_table_data
+                .iter()
+                .map(|(key, values)| (Some(key), values))
+                .collect(),
+            TableData::FlatArray(arr_table_data) => {
+                arr_table_data.iter().map(|values| (None, values)).collect()
+            }
+        }
+    }
+
+    /// Renders the rows of the table, one line per row (or, minified, one after the other)
+    fn lua_rows(
+        lua_format: &LuaFormat,
+        rows: &[Row],
+        indent: usize,
+    ) -> Result<Vec<String>, Wh2LuaError> {
+        let mut result = Vec::new();
+
+        for (key, values) in rows.iter() {
+            let mut row = lua_format.indent(indent);
+            if let Some(key) = key {
+                row.push_str(&format!("{}{}", lua_format.key(key), lua_format.assign()));
+            }
+            row.push_str(&format!("{{{}", lua_format.space()));
             for (k, v) in values.iter() {
-                result.push_str(&Self::lua_key_value_entry(k, v));
+                row.push_str(&Self::lua_key_value_entry(lua_format, k, v));
             }
-            result.push_str("},\n");
+            row.push_str(&format!("}},{}", lua_format.newline()));
+            result.push(row);
         }
 
         Ok(result)
     }
 
-    fn lua_array_table(
-        arr_table_data: &[Vec<(LuaValue, LuaValue)>],
-        indent: usize,
-    ) -> Result<String, Wh2LuaError> {
+    fn lua_key_value_entry(lua_format: &LuaFormat, key: &LuaValue, value: &LuaValue) -> String {
+        format!(
+            "{}{}{},{}",
+            lua_format.key(key),
+            lua_format.assign(),
+            lua_format.value(value),
+            lua_format.space()
+        )
+    }
+}
+
+/// How the values in a generated script are written: pretty-printed (the default) or minified (see `Config::minify`),
+/// and with which strings interned (see `Config::intern_strings`)
+struct LuaFormat {
+    minify: bool,
+    /// The interned strings, most frequent first
+    interned: Vec<String>,
+    /// Interned string -> the Lua expression referring to it
+    references: HashMap<String, String>,
+}
+
+impl LuaFormat {
+    fn new(config: &Config, rows: &[Row]) -> Self {
+        let interned = match config.intern_strings {
+            Some(min_count) => Self::strings_to_intern(rows, min_count),
+            None => Vec::new(),
+        };
+        let references = interned
+            .iter()
+            .enumerate()
+            .map(|(index, text)| {
+                let reference = if index < MAX_INTERNED_LOCALS {
+                    format!("s{}", index + 1)
+                } else {
+                    format!("S[{}]", index - MAX_INTERNED_LOCALS + 1)
+                };
+                (text.clone(), reference)
+            })
+            .collect();
+        Self {
+            minify: config.minify,
+            interned,
+            references,
+        }
+    }
+
+    /// Returns the string values that occur at least `min_count` times in the rows, most frequent first. Strings that
+    /// are too short to gain anything from interning are left alone.
+    fn strings_to_intern(rows: &[Row], min_count: usize) -> Vec<String> {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for (_, values) in rows.iter() {
+            for (_, value) in values.iter() {
+                if let LuaValue::Text(text) = value {
+                    *counts.entry(text.as_str()).or_insert(0) += 1;
+                }
+            }
+        }
+        let mut strings: Vec<(&str, usize)> = counts
+            .into_iter()
+            .filter(|(text, count)| *count >= min_count && text.len() > 4)
+            .collect();
+        strings.sort_by(|(a_text, a_count), (b_text, b_count)| {
+            b_count.cmp(a_count).then(a_text.cmp(b_text))
+        });
+        strings
+            .into_iter()
+            .map(|(text, _)| text.to_string())
+            .collect()
+    }
+
+    /// The declarations of the interned strings, to be placed at the top of the script: a local for each of the most
+    /// frequent strings, and a lookup table for the rest
+    fn declarations(&self) -> String {
+        if self.interned.is_empty() {
+            return String::new();
+        }
+        let text = |value: &String| LuaValue::Text(value.clone()).to_lua_value();
+
         let mut result = String::new();
-        for row in arr_table_data {
-            result.push_str(&format!("{}{{ ", "  ".repeat(indent)));
-            for (k, v) in row {
-                result.push_str(&Self::lua_key_value_entry(k, v));
+        for (index, value) in self.interned.iter().take(MAX_INTERNED_LOCALS).enumerate() {
+            result.push_str(&format!(
+                "local s{}{}{}\n",
+                index + 1,
+                self.assign(),
+                text(value)
+            ));
+        }
+        if self.interned.len() > MAX_INTERNED_LOCALS {
+            result.push_str(&format!("local S{}{{{}", self.assign(), self.newline()));
+            for value in self.interned.iter().skip(MAX_INTERNED_LOCALS) {
+                result.push_str(&format!(
+                    "{}{},{}",
+                    self.indent(1),
+                    text(value),
+                    self.newline()
+                ));
             }
-            result.push_str("},\n");
+            result.push_str("}\n");
+        }
+        result.push_str(self.newline());
+        result
+    }
+
+    /// A value in a table constructor, or a reference to it if it is interned
+    fn value(&self, value: &LuaValue) -> String {
+        match value {
+            LuaValue::Text(text) => match self.references.get(text) {
+                Some(reference) => reference.clone(),
+                None => value.to_lua_value(),
+            },
+            _ => value.to_lua_value(),
+        }
+    }
+
+    fn indent(&self, level: usize) -> String {
+        if self.minify {
+            String::new()
+        } else {
+            "  ".repeat(level)
         }
-        Ok(result)
     }
 
-    fn lua_key_value_entry(key: &LuaValue, value: &LuaValue) -> String {
-        format!("[{}] = {}, ", key.to_lua_value(), value.to_lua_value())
+    fn newline(&self) -> &'static str {
+        if self.minify {
+            ""
+        } else {
+            "\n"
+        }
+    }
+
+    fn space(&self) -> &'static str {
+        if self.minify {
+            ""
+        } else {
+            " "
+        }
+    }
+
+    fn assign(&self) -> &'static str {
+        if self.minify {
+            "="
+        } else {
+            " = "
+        }
+    }
+
+    /// A key in a table constructor. Minified, keys that are valid Lua identifiers are written bare (`key=value`
+    /// instead of `["key"] = value`).
+    fn key(&self, key: &LuaValue) -> String {
+        match key {
+            LuaValue::Text(name) if self.minify && Self::is_identifier(name) => name.clone(),
+            _ => format!("[{}]", key.to_lua_value()),
+        }
+    }
+
+    fn is_identifier(name: &str) -> bool {
+        const KEYWORDS: &[&str] = &[
+            "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "if", "in",
+            "local", "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+        ];
+        let mut chars = name.chars();
+        match chars.next() {
+            Some(first) if first.is_ascii_alphabetic() || first == '_' => {
+                chars.all(|c| c.is_ascii_alphanumeric() || c == '_') && !KEYWORDS.contains(&name)
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rpfm_lib::schema::Schema;
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+
+    fn text(value: &str) -> LuaValue {
+        LuaValue::Text(value.to_string())
+    }
+
+    fn test_config() -> Config {
+        Config::new(Schema::default(), PathBuf::from("out"))
+    }
+
+    /// A table with a row per (key, value) pair
+    fn kv_table(rows: &[(String, String)]) -> TotalWarDbPreProcessed {
+        let data: BTreeMap<LuaValue, Vec<(LuaValue, LuaValue)>> = rows
+            .iter()
+            .map(|(key, value)| {
+                (
+                    text(key),
+                    vec![(text("key"), text(key)), (text("value"), text(value))],
+                )
+            })
+            .collect();
+        TotalWarDbPreProcessed::new(
+            "units_tables",
+            TableData::KeyValue(data),
+            vec!["key".to_string()],
+            Some(1),
+            vec![
+                "lua_db".to_string(),
+                "mod".to_string(),
+                "units_tables".to_string(),
+                "my.mod_data__.lua".to_string(),
+            ],
+        )
+    }
+
+    /// The row lines of a script: the lines that start a row, like `  ["key"] = { ... },`
+    fn row_lines(script: &str) -> Vec<&str> {
+        script
+            .lines()
+            .filter(|line| line.trim_start().starts_with("[\""))
+            .collect()
+    }
+
+    #[test]
+    fn chunks_contain_same_rows_as_unchunked_script() {
+        let rows: Vec<(String, String)> = (1..=5)
+            .map(|index| (format!("unit_{}", index), format!("category_{}", index)))
+            .collect();
+        let table = kv_table(&rows);
+
+        let mut config = test_config();
+        let unchunked = LuaWriter {}.render_table_files(&config, &table).unwrap();
+        assert_eq!(unchunked.len(), 1);
+
+        config.chunk_rows = Some(2);
+        let files = LuaWriter {}.render_table_files(&config, &table).unwrap();
+        // The stitcher script, and 3 chunks
+        assert_eq!(files.len(), 4);
+        assert_eq!(files[0].0, table.script_file_path);
+
+        let chunk_rows: Vec<&str> = files[1..]
+            .iter()
+            .flat_map(|(_, script)| row_lines(script))
+            .collect();
+        assert_eq!(chunk_rows, row_lines(&unchunked[0].1));
+
+        // The stitcher requires every chunk, by a path without dots (require treats them as separators)
+        for (chunk_path, _) in files[1..].iter() {
+            assert_eq!(
+                chunk_path[..2],
+                ["lua_db".to_string(), "_chunks".to_string()]
+            );
+            let require_path = chunk_path.join("/");
+            let require_path = require_path.trim_end_matches(".lua");
+            assert!(!require_path.contains('.'));
+            assert!(files[0].1.contains(&format!("\"{}\"", require_path)));
+        }
+    }
+
+    #[test]
+    fn recognizes_identifiers() {
+        assert!(LuaFormat::is_identifier("category"));
+        assert!(LuaFormat::is_identifier("_unit_1"));
+        assert!(!LuaFormat::is_identifier(""));
+        assert!(!LuaFormat::is_identifier("1st_unit"));
+        assert!(!LuaFormat::is_identifier("unit-key"));
+        assert!(!LuaFormat::is_identifier("end"));
+        assert!(!LuaFormat::is_identifier("function"));
+    }
+
+    #[test]
+    fn minified_keywords_are_not_written_bare() {
+        let mut config = test_config();
+        config.minify = true;
+        let lua_format = LuaFormat::new(&config, &[]);
+        assert_eq!(lua_format.key(&text("category")), "category");
+        assert_eq!(lua_format.key(&text("end")), "[\"end\"]");
+    }
+
+    #[test]
+    fn interned_strings_beyond_local_limit_go_into_lookup_table() {
+        // 190 strings that occur twice each
+        let rows: Vec<(String, String)> = (0..380)
+            .map(|index| {
+                (
+                    format!("unit_{:03}", index),
+                    format!("value_{:03}", index / 2),
+                )
+            })
+            .collect();
+        let table = kv_table(&rows);
+
+        let mut config = test_config();
+        config.intern_strings = Some(2);
+        let script = LuaWriter::convert_tw_db_to_lua_script(&config, &table).unwrap();
+
+        assert!(script.contains("local s1 = \"value_000\"\n"));
+        assert!(script.contains("local s180 = \"value_179\"\n"));
+        assert!(!script.contains("local s181 "));
+        assert!(script.contains("local S = {\n"));
+        assert!(script.contains("[\"value\"] = s1,"));
+        // The 190th string is the 10th one in the lookup table
+        assert!(script.contains("[\"value\"] = S[10],"));
+        assert!(!script.contains("= \"value_189\""));
     }
 }
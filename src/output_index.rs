@@ -0,0 +1,118 @@
+use std::collections::BTreeMap;
+
+use crate::config::{OutputLayout, ScriptFolder};
+use crate::tw_db_pp::{LuaValue, TotalWarDbPreProcessed};
+
+/// A generated script, as listed in the index
+struct IndexEntry {
+    /// The script path without extension, relative to the pack script root. Dots in the file name are kept, so unlike
+    /// the support file paths, this cannot always be passed to require directly.
+    script: String,
+    /// The packfile (or input directory) the script was generated from
+    source: String,
+}
+
+/// The generated scripts of a single db table, per folder, in load order
+#[derive(Default)]
+struct TableIndex {
+    core: Option<IndexEntry>,
+    mod_core: Vec<IndexEntry>,
+    mods: Vec<IndexEntry>,
+}
+
+/// Lists, per db table, the core script and all mod fragments that were generated, in load order.
+///
+/// Written as index.lua in the root folder of the generated scripts (lua_db/index.lua by default), so the game can
+/// load exactly the generated scripts, in the right order, without having to probe the VFS.
+pub struct OutputIndex {
+    tables: BTreeMap<String, TableIndex>,
+}
+
+impl Default for OutputIndex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OutputIndex {
+    pub fn new() -> Self {
+        Self {
+            tables: BTreeMap::new(),
+        }
+    }
+
+    /// Path of the index (relative to the output root, like the generated scripts)
+    pub fn path(layout: &OutputLayout) -> Vec<String> {
+        layout.root_file_path("index.lua")
+    }
+
+    /// Adds the script generated for a table, listed by its actual path, including any dots, as the script files are
+    /// not renamed (unlike the support files, see OutputLayout::support_file_path). Scripts must be added in load order.
+    /// A script with the same path from an earlier source is replaced, and moves to the position of the later source.
+    pub fn add_script(&mut self, source: &str, table: &TotalWarDbPreProcessed) {
+        let mut script_path = table.script_file_path.clone();
+        let file_name = script_path.pop().unwrap();
+        // Drop file extension
+        let file_stem = match file_name.rfind('.') {
+            Some(index) => file_name[..index].to_string(),
+            None => file_name,
+        };
+        script_path.push(file_stem);
+
+        let entry = IndexEntry {
+            script: script_path.join("/"),
+            source: source.to_string(),
+        };
+
+        let table_index = self
+            .tables
+            .entry(table.table_name.clone())
+            .or_insert_with(TableIndex::default);
+
+        match table.folder {
+            ScriptFolder::Core => table_index.core = Some(entry),
+            ScriptFolder::ModCore => Self::push_entry(&mut table_index.mod_core, entry),
+            ScriptFolder::Mod => Self::push_entry(&mut table_index.mods, entry),
+        }
+    }
+
+    fn push_entry(entries: &mut Vec<IndexEntry>, entry: IndexEntry) {
+        entries.retain(|existing| existing.script != entry.script);
+        entries.push(entry);
+    }
+
+    pub fn to_lua(&self) -> String {
+        let text = |value: &str| LuaValue::Text(value.to_string()).to_lua_value();
+        let lua_entry = |entry: &IndexEntry| {
+            format!(
+                "{{ script = {}, source = {} }}",
+                text(&entry.script),
+                text(&entry.source)
+            )
+        };
+
+        let mut result = String::new();
+        result.push_str("return {\n");
+        for (table_name, table_index) in self.tables.iter() {
+            result.push_str(&format!("  [{}] = {{\n", text(table_name)));
+            if let Some(core) = &table_index.core {
+                result.push_str(&format!("    core = {},\n", lua_entry(core)));
+            }
+            for (folder, entries) in [
+                ("mod_core", &table_index.mod_core),
+                ("mod", &table_index.mods),
+            ]
+            .iter()
+            {
+                result.push_str(&format!("    {} = {{\n", folder));
+                for entry in entries.iter() {
+                    result.push_str(&format!("      {},\n", lua_entry(entry)));
+                }
+                result.push_str("    },\n");
+            }
+            result.push_str("  },\n");
+        }
+        result.push('}');
+        result
+    }
+}
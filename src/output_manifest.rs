@@ -0,0 +1,111 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use sha2::{Digest, Sha256};
+
+use rpfm_lib::schema::Schema;
+
+use crate::config::OutputLayout;
+use crate::tw_db_pp::{LuaValue, TotalWarDbPreProcessed};
+
+/// Lists every generated file (scripts, and the chunks and reverse indexes generated for them), with where it came from
+/// and a hash of its contents.
+///
+/// Written alongside the generated scripts, both as JSON and as a Lua script returning the same data.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OutputManifest {
+    /// The version of this tool
+    pub tool_version: String,
+    /// The version of the RPFM schema the tables were decoded with
+    pub schema_version: u16,
+    /// File path (joined with "/") -> entry
+    pub files: BTreeMap<String, OutputManifestEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OutputManifestEntry {
+    /// The packfile (or input directory) the table came from
+    pub source: String,
+    pub table_name: String,
+    /// The version of the table's schema definition, if it had one
+    pub table_version: Option<i32>,
+    /// The number of rows in the table (not only in the file, for chunks)
+    pub row_count: usize,
+    /// Lowercase hex SHA-256 of the file contents
+    pub sha256: String,
+}
+
+impl OutputManifest {
+    /// Creates an empty manifest for tables decoded with the given schema
+    pub fn new(schema: &Schema) -> Self {
+        Self {
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            schema_version: *schema.get_ref_version(),
+            files: BTreeMap::new(),
+        }
+    }
+
+    /// Path of the JSON manifest, for tooling (relative to the output root, like the generated scripts)
+    pub fn json_path(layout: &OutputLayout) -> Vec<String> {
+        layout.root_file_path("manifest.json")
+    }
+
+    /// Path of the Lua manifest, for the game
+    pub fn lua_path(layout: &OutputLayout) -> Vec<String> {
+        layout.root_file_path("manifest.lua")
+    }
+
+    /// Adds a file generated for a table. A file with the same path from an earlier source is replaced.
+    pub fn add_file(
+        &mut self,
+        source: &str,
+        table: &TotalWarDbPreProcessed,
+        path: &[String],
+        contents: &str,
+    ) {
+        self.files.insert(
+            path.join("/"),
+            OutputManifestEntry {
+                source: source.to_string(),
+                table_name: table.table_name.clone(),
+                table_version: table.version,
+                row_count: table.row_count(),
+                sha256: format!("{:x}", Sha256::digest(contents.as_bytes())),
+            },
+        );
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn to_lua(&self) -> String {
+        let text = |value: &str| LuaValue::Text(value.to_string()).to_lua_value();
+
+        let mut result = String::new();
+        result.push_str("return {\n");
+        result.push_str(&format!("  tool_version = {},\n", text(&self.tool_version)));
+        result.push_str(&format!("  schema_version = {},\n", self.schema_version));
+        result.push_str("  files = {\n");
+        for (path, entry) in self.files.iter() {
+            result.push_str(&format!(
+                "    [{}] = {{ source = {}, table_name = {}, ",
+                text(path),
+                text(&entry.source),
+                text(&entry.table_name)
+            ));
+            if let Some(table_version) = entry.table_version {
+                result.push_str(&format!("table_version = {}, ", table_version));
+            }
+            result.push_str(&format!(
+                "row_count = {}, sha256 = {} }},\n",
+                entry.row_count,
+                text(&entry.sha256)
+            ));
+        }
+        result.push_str("  },\n");
+        result.push('}');
+        result
+    }
+}
@@ -0,0 +1,384 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{stdout, Write};
+use std::path::{Path, PathBuf};
+
+use zip::write::FileOptions;
+use zip::ZipWriter;
+
+use crate::config::{Config, OutputSinkKind, PackSettings};
+use crate::log::Log;
+use crate::rpfm::Rpfm;
+use crate::wh2_lua_error::Wh2LuaError;
+
+/// A destination for generated files.
+///
+/// Paths are relative to the root of the destination, as a list of path components
+/// (e.g. `["lua_db", "mod", "land_units_tables", "my_mod.lua"]`).
+pub trait OutputSink {
+    /// Returns a human readable representation of a path in this destination, for logging
+    fn display_path(&self, path: &[String]) -> String;
+
+    /// Returns true if a file already exists at the given path from a previous run, and writing it would overwrite it
+    fn exists(&self, path: &[String]) -> bool;
+
+    /// Writes a single file. Writing the same path twice replaces the earlier contents.
+    fn write_file(&mut self, path: &[String], contents: &str) -> Result<(), Wh2LuaError>;
+
+    /// Finishes writing, e.g. by saving the packfile or archive. No files can be written afterwards.
+    fn finish(&mut self) -> Result<(), Wh2LuaError>;
+}
+
+/// Creates the output sink selected in the config
+pub fn create_output_sink(config: &Config) -> Box<dyn OutputSink> {
+    match config.sink {
+        OutputSinkKind::Directory => Box::new(DirectorySink::new(config.out_dir.clone())),
+        OutputSinkKind::Packfile => Box::new(PackfileSink::new(
+            output_archive_path(config).unwrap(),
+            config.pack.clone(),
+            config.layout.pack_script_root.clone(),
+        )),
+        OutputSinkKind::Zip => Box::new(ZipSink::new(output_archive_path(config).unwrap())),
+        OutputSinkKind::Stdout => Box::new(StdoutSink {}),
+    }
+}
+
+/// Returns the path of the generated packfile or zip archive, or None if the selected sink does not generate a single file
+pub fn output_archive_path(config: &Config) -> Option<PathBuf> {
+    archive_path(config.sink, &config.out_dir, &config.pack)
+}
+
+/// Returns the path of the packfile or zip archive the given sink generates in the output directory, or None if the
+/// sink does not generate a single file
+pub fn archive_path(sink: OutputSinkKind, out_dir: &Path, pack: &PackSettings) -> Option<PathBuf> {
+    match sink {
+        OutputSinkKind::Packfile => Some(out_dir.join(&pack.name)),
+        OutputSinkKind::Zip => Some(out_dir.join(&pack.name).with_extension("zip")),
+        OutputSinkKind::Directory | OutputSinkKind::Stdout => None,
+    }
+}
+
+/// The path of the backup of the previously generated file, e.g. lua_db_generated.pack.bak
+pub fn backup_path(path: &Path) -> PathBuf {
+    path_with_suffix(path, "bak")
+}
+
+/// The temporary path a file is written to before being moved into place, e.g. lua_db_generated.pack.tmp
+fn temp_path(path: &Path) -> PathBuf {
+    path_with_suffix(path, "tmp")
+}
+
+fn path_with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut file_name = path.file_name().unwrap().to_os_string();
+    file_name.push(".");
+    file_name.push(suffix);
+    path.with_file_name(file_name)
+}
+
+/// Moves a completely written temporary file into place. The file it replaces (if any) is kept as a backup, and is put
+/// back if the temporary file cannot be moved.
+fn replace_keeping_backup(temp_path: &Path, path: &Path) -> Result<(), Wh2LuaError> {
+    let backup_path = backup_path(path);
+    let has_backup = path.exists();
+    if has_backup {
+        Log::debug(&format!("Keeping backup: {}", backup_path.display()));
+        fs::rename(path, &backup_path)?;
+    }
+
+    if let Err(error) = fs::rename(temp_path, path) {
+        if has_backup {
+            fs::rename(&backup_path, path)?;
+        }
+        let _ = fs::remove_file(temp_path);
+        return Err(error.into());
+    }
+    Ok(())
+}
+
+/// Restores the backup of the packfile or zip archive previously generated with the given sink in the output directory,
+/// replacing the current one.
+///
+/// Only needs the output settings, not a whole `Config`, so restoring does not depend on the schema or the input files.
+pub fn restore_backup(
+    sink: OutputSinkKind,
+    out_dir: &Path,
+    pack: &PackSettings,
+) -> Result<(), Wh2LuaError> {
+    let path = archive_path(sink, out_dir, pack).ok_or_else(|| {
+        Wh2LuaError::ConfigError(
+            "Backups are only kept for generated packfiles and zip archives".to_string(),
+        )
+    })?;
+    let backup_path = backup_path(&path);
+    if !backup_path.exists() {
+        return Err(Wh2LuaError::ConfigError(format!(
+            "No backup found at {}",
+            backup_path.display()
+        )));
+    }
+
+    Log::info(&format!(
+        "Restoring {} from backup {}",
+        path.display(),
+        backup_path.display()
+    ));
+    fs::rename(&backup_path, &path)?;
+    Ok(())
+}
+
+/// Writes files directly to disk, under the output directory.
+///
+/// Each file is written to a temporary file next to it right away, and only once all files have been written, they
+/// are moved into place. The files they replace are kept as backups until then, so if moving any file fails, all files
+/// moved so far are put back. A failure therefore never leaves partially written files, or a mix of old and new files,
+/// behind.
+pub struct DirectorySink {
+    out_dir: PathBuf,
+    /// Final path -> temporary path of every file written so far
+    temp_files: BTreeMap<PathBuf, PathBuf>,
+}
+
+impl DirectorySink {
+    pub fn new(out_dir: PathBuf) -> Self {
+        Self {
+            out_dir,
+            temp_files: BTreeMap::new(),
+        }
+    }
+
+    fn file_path(&self, path: &[String]) -> PathBuf {
+        let mut file_path = self.out_dir.clone();
+        path.iter().for_each(|e| file_path.push(e));
+        file_path
+    }
+
+    /// Moves all temporary files into place. Every file that was moved is added to `moved_files`, with whether the
+    /// file it replaced was kept as a backup.
+    fn move_temp_files(
+        &mut self,
+        moved_files: &mut Vec<(PathBuf, bool)>,
+    ) -> Result<(), Wh2LuaError> {
+        let mut temp_files = std::mem::take(&mut self.temp_files).into_iter();
+        while let Some((file_path, temp_file_path)) = temp_files.next() {
+            let has_backup = file_path.exists();
+            let moved = if has_backup {
+                fs::rename(&file_path, backup_path(&file_path))
+            } else {
+                Ok(())
+            }
+            .and_then(|_| fs::rename(&temp_file_path, &file_path));
+
+            if let Err(error) = moved {
+                let _ = fs::remove_file(&temp_file_path);
+                if has_backup && !file_path.exists() {
+                    let _ = fs::rename(backup_path(&file_path), &file_path);
+                }
+                // Not moved, so removed with the other temporary files
+                self.temp_files.extend(temp_files);
+                return Err(error.into());
+            }
+            moved_files.push((file_path, has_backup));
+        }
+        Ok(())
+    }
+
+    /// Removes all temporary files that were not moved into place
+    fn remove_temp_files(&mut self) {
+        for (_, temp_file_path) in std::mem::take(&mut self.temp_files) {
+            let _ = fs::remove_file(temp_file_path);
+        }
+    }
+}
+
+impl OutputSink for DirectorySink {
+    fn display_path(&self, path: &[String]) -> String {
+        format!("{}", self.file_path(path).display())
+    }
+
+    fn exists(&self, path: &[String]) -> bool {
+        self.file_path(path).exists()
+    }
+
+    fn write_file(&mut self, path: &[String], contents: &str) -> Result<(), Wh2LuaError> {
+        let file_path = self.file_path(path);
+        fs::create_dir_all(&file_path.parent().unwrap())?;
+
+        let temp_file_path = temp_path(&file_path);
+        Log::debug(&format!("Writing file: {}", temp_file_path.display()));
+        self.temp_files.insert(file_path, temp_file_path.clone());
+        fs::write(&temp_file_path, contents)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Wh2LuaError> {
+        // final path, and whether the file it replaced was kept as a backup
+        let mut moved_files = Vec::new();
+        let result = self.move_temp_files(&mut moved_files);
+        if result.is_err() {
+            self.remove_temp_files();
+        }
+
+        for (file_path, has_backup) in moved_files.into_iter().rev() {
+            let backup_file_path = backup_path(&file_path);
+            match (&result, has_backup) {
+                (Ok(_), true) => {
+                    let _ = fs::remove_file(backup_file_path);
+                }
+                (Ok(_), false) => {}
+                // Roll back
+                (Err(_), true) => {
+                    let _ = fs::rename(backup_file_path, &file_path);
+                }
+                (Err(_), false) => {
+                    let _ = fs::remove_file(&file_path);
+                }
+            }
+        }
+        result
+    }
+}
+
+impl Drop for DirectorySink {
+    /// Cleans up after a run that failed before the sink was finished
+    fn drop(&mut self) {
+        self.remove_temp_files();
+    }
+}
+
+/// Collects files into a (movie) packfile, which is saved when finished.
+/// The previous packfile is kept as a backup (see [`restore_backup`]).
+pub struct PackfileSink {
+    packfile_path: PathBuf,
+    pack_settings: PackSettings,
+    /// The folder in the packfile containing the files, e.g. "script"
+    script_root: String,
+    files: BTreeMap<Vec<String>, String>,
+}
+
+impl PackfileSink {
+    pub fn new(packfile_path: PathBuf, pack_settings: PackSettings, script_root: String) -> Self {
+        Self {
+            packfile_path,
+            pack_settings,
+            script_root,
+            files: BTreeMap::new(),
+        }
+    }
+}
+
+impl OutputSink for PackfileSink {
+    fn display_path(&self, path: &[String]) -> String {
+        format!(
+            "{}:{}/{}",
+            self.packfile_path.display(),
+            self.script_root,
+            path.join("/")
+        )
+    }
+
+    fn exists(&self, _path: &[String]) -> bool {
+        // The packfile is always generated from scratch
+        false
+    }
+
+    fn write_file(&mut self, path: &[String], contents: &str) -> Result<(), Wh2LuaError> {
+        self.files.insert(path.to_vec(), contents.to_string());
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Wh2LuaError> {
+        let mut packfile = Rpfm::generate_packfile_with_script(
+            &self.files,
+            &self.pack_settings,
+            &self.script_root,
+        )?;
+        let temp_packfile_path = temp_path(&self.packfile_path);
+        Log::debug(&format!(
+            "Saving packfile: {}",
+            temp_packfile_path.display()
+        ));
+        if let Err(error) = packfile.save(Some(temp_packfile_path.clone())) {
+            let _ = fs::remove_file(&temp_packfile_path);
+            return Err(error.into());
+        }
+        replace_keeping_backup(&temp_packfile_path, &self.packfile_path)
+    }
+}
+
+/// Collects files into a zip archive, with the same layout as the directory output, which is saved when finished.
+/// The previous archive is kept as a backup (see [`restore_backup`]).
+pub struct ZipSink {
+    zip_path: PathBuf,
+    files: BTreeMap<Vec<String>, String>,
+}
+
+impl ZipSink {
+    pub fn new(zip_path: PathBuf) -> Self {
+        Self {
+            zip_path,
+            files: BTreeMap::new(),
+        }
+    }
+
+    fn write_zip(&self, zip_path: &Path) -> Result<(), Wh2LuaError> {
+        let mut zip = ZipWriter::new(fs::File::create(zip_path)?);
+        for (path, contents) in self.files.iter() {
+            zip.start_file(path.join("/"), FileOptions::default())?;
+            zip.write_all(contents.as_bytes())?;
+        }
+        zip.finish()?;
+        Ok(())
+    }
+}
+
+impl OutputSink for ZipSink {
+    fn display_path(&self, path: &[String]) -> String {
+        format!("{}:{}", self.zip_path.display(), path.join("/"))
+    }
+
+    fn exists(&self, _path: &[String]) -> bool {
+        // The archive is always generated from scratch
+        false
+    }
+
+    fn write_file(&mut self, path: &[String], contents: &str) -> Result<(), Wh2LuaError> {
+        self.files.insert(path.to_vec(), contents.to_string());
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Wh2LuaError> {
+        let temp_zip_path = temp_path(&self.zip_path);
+        Log::debug(&format!("Saving zip archive: {}", temp_zip_path.display()));
+        if let Err(error) = self.write_zip(&temp_zip_path) {
+            let _ = fs::remove_file(&temp_zip_path);
+            return Err(error);
+        }
+        replace_keeping_backup(&temp_zip_path, &self.zip_path)
+    }
+}
+
+/// Streams files to stdout, each preceded by a "==> path <==" header line
+pub struct StdoutSink {}
+
+impl OutputSink for StdoutSink {
+    fn display_path(&self, path: &[String]) -> String {
+        path.join("/")
+    }
+
+    fn exists(&self, _path: &[String]) -> bool {
+        false
+    }
+
+    fn write_file(&mut self, path: &[String], contents: &str) -> Result<(), Wh2LuaError> {
+        let stdout = stdout();
+        let mut handle = stdout.lock();
+        writeln!(handle, "==> {} <==", path.join("/"))?;
+        writeln!(handle, "{}", contents)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Wh2LuaError> {
+        stdout().flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::BTreeMap;
+
+use crate::config::OutputLayout;
+use crate::tw_db_pp::{Column, TableData, TotalWarDbPreProcessed};
+
+/// The row shape of a single db table
+struct TableStub {
+    columns: Vec<Column>,
+    /// The Lua type of the key, if the rows are keyed by a single key column (instead of being in an array)
+    key_type: Option<&'static str>,
+}
+
+/// EmmyLua / LuaLS type annotations for the rows of all generated db tables.
+///
+/// Written as types.lua in the root folder of the generated scripts. The file only contains annotations, so it does
+/// nothing when loaded by the game, but gives autocomplete and type checking on rows when added to the workspace (or
+/// library) of the Lua language server.
+pub struct OutputStubs {
+    tables: BTreeMap<String, TableStub>,
+}
+
+impl Default for OutputStubs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OutputStubs {
+    pub fn new() -> Self {
+        Self {
+            tables: BTreeMap::new(),
+        }
+    }
+
+    /// Path of the stub file (relative to the output root, like the generated scripts)
+    pub fn path(layout: &OutputLayout) -> Vec<String> {
+        layout.root_file_path("types.lua")
+    }
+
+    /// Adds the row shape of a table. Tables without a definition in the schema are skipped. If the same table is
+    /// added more than once, the last one (in load order) is used.
+    pub fn add_table(&mut self, table: &TotalWarDbPreProcessed) {
+        if table.columns.is_empty() {
+            return;
+        }
+        let key_type = match &table.data {
+            TableData::KeyValue(_) => table.key_columns.first().and_then(|key_column| {
+                table
+                    .columns
+                    .iter()
+                    .find(|column| &column.name == key_column)
+                    .map(|column| column.lua_type)
+            }),
+            TableData::FlatArray(_) => None,
+        };
+        self.tables.insert(
+            table.table_name.clone(),
+            TableStub {
+                columns: table.columns.clone(),
+                key_type,
+            },
+        );
+    }
+
+    /// The class name of a row of the given table
+    fn class_name(table_name: &str) -> String {
+        format!("LuaDB.{}", table_name)
+    }
+
+    pub fn to_lua(&self) -> String {
+        let mut result = String::new();
+        result.push_str("---@meta\n");
+        result.push_str(
+            "-- Type annotations for the generated LuaDB tables, for the Lua language server.\n",
+        );
+        for (table_name, stub) in self.tables.iter() {
+            let class_name = Self::class_name(table_name);
+            result.push_str(&format!("\n---A row of the {} db table\n", table_name));
+            result.push_str(&format!("---@class {}\n", class_name));
+            for column in stub.columns.iter() {
+                result.push_str(&format!(
+                    "---@field {} {}{}\n",
+                    column.name,
+                    column.lua_type,
+                    if column.nillable { "?" } else { "" }
+                ));
+            }
+            let table_type = match stub.key_type {
+                Some(key_type) => format!("table<{}, {}>", key_type, class_name),
+                None => format!("{}[]", class_name),
+            };
+            result.push_str(&format!(
+                "\n---The data returned by the scripts generated for the {} db table\n",
+                table_name
+            ));
+            result.push_str(&format!("---@alias {}_table {}\n", class_name, table_type));
+        }
+        result
+    }
+}
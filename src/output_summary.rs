@@ -0,0 +1,55 @@
+use crate::config::Config;
+use crate::output_index::OutputIndex;
+use crate::output_manifest::OutputManifest;
+use crate::output_stubs::OutputStubs;
+use crate::tw_db_pp::TotalWarDbPreProcessed;
+
+/// The files that describe the generated output as a whole (the manifest, index and type stubs), built up table by
+/// table.
+///
+/// Used by both a regular run and watch mode, so the output always contains the same files.
+pub struct OutputSummary {
+    manifest: OutputManifest,
+    index: OutputIndex,
+    stubs: OutputStubs,
+}
+
+impl OutputSummary {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            manifest: OutputManifest::new(&config.schema),
+            index: OutputIndex::new(),
+            stubs: OutputStubs::new(),
+        }
+    }
+
+    /// Adds a table, with all files generated for it (see `render_table_files`). Tables must be added in load order.
+    pub fn add_table(
+        &mut self,
+        source: &str,
+        table: &TotalWarDbPreProcessed,
+        files: &[(Vec<String>, String)],
+    ) {
+        for (path, contents) in files.iter() {
+            self.manifest.add_file(source, table, path, contents);
+        }
+        self.index.add_script(source, table);
+        self.stubs.add_table(table);
+    }
+
+    /// Renders the summary files, with their paths (relative to the output root, like the generated scripts)
+    pub fn files(&self, config: &Config) -> Vec<(Vec<String>, String)> {
+        vec![
+            (
+                OutputManifest::json_path(&config.layout),
+                self.manifest.to_json(),
+            ),
+            (
+                OutputManifest::lua_path(&config.layout),
+                self.manifest.to_lua(),
+            ),
+            (OutputIndex::path(&config.layout), self.index.to_lua()),
+            (OutputStubs::path(&config.layout), self.stubs.to_lua()),
+        ]
+    }
+}
@@ -0,0 +1,60 @@
+use crate::config::Config;
+use crate::lua_writer::LuaWriter;
+use crate::tw_db_pp::TotalWarDbPreProcessed;
+use crate::wh2_lua_error::Wh2LuaError;
+
+/// Renders pre-processed db tables to a specific output format.
+///
+/// Implementations only deal with turning a single table into text. Loading the tables and writing the results
+/// somewhere is left to the rest of the pipeline.
+pub trait OutputWriter {
+    /// The name of the format, as selected with --format
+    fn name(&self) -> &'static str;
+
+    /// The extension (without the dot) of the generated files
+    fn file_extension(&self) -> &'static str;
+
+    /// Renders a single pre-processed table
+    fn render_table(
+        &self,
+        config: &Config,
+        table: &TotalWarDbPreProcessed,
+    ) -> Result<String, Wh2LuaError>;
+
+    /// Renders a single pre-processed table into the files for it: the script at the table's script path, plus any
+    /// other files that script needs, with their paths (relative to the output root, like the script path). By default,
+    /// this is only the result of `render_table`.
+    fn render_table_files(
+        &self,
+        config: &Config,
+        table: &TotalWarDbPreProcessed,
+    ) -> Result<Vec<(Vec<String>, String)>, Wh2LuaError> {
+        Ok(vec![(
+            table.script_file_path.clone(),
+            self.render_table(config, table)?,
+        )])
+    }
+}
+
+/// Returns all available output writers. To add a new output format, add its writer here.
+pub fn output_writers() -> Vec<Box<dyn OutputWriter>> {
+    vec![Box::new(LuaWriter {})]
+}
+
+/// Returns the output writer for the format with the given name.
+pub fn find_output_writer(format: &str) -> Result<Box<dyn OutputWriter>, Wh2LuaError> {
+    output_writers()
+        .into_iter()
+        .find(|writer| writer.name() == format)
+        .ok_or_else(|| {
+            Wh2LuaError::ConfigError(format!(
+                "Unknown output format: {} (available formats: {})",
+                format,
+                output_writers()
+                    .iter()
+                    .map(|writer| writer.name())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))
+        })
+}
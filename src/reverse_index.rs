@@ -0,0 +1,80 @@
+use crate::config::{Config, OutputLayout};
+use crate::tw_db_pp::{ReverseIndexData, TotalWarDbPreProcessed};
+
+/// The folder in the root folder that contains all reverse indexes (see `OutputLayout::support_file_path`)
+const REVERSE_INDEX_FOLDER: &str = "_reverse_indexes";
+
+/// A reverse index on a column of a db table: column value -> the keys of all rows with that value. For tables that are
+/// generated as an array (i.e. that do not have a single key column), the row numbers are used instead of keys.
+///
+/// Built while the table is converted (see `Rpfm::convert_rpfm_db_to_preprocessed_db`), so rows in which the field is
+/// left out of the generated script are indexed as well. Written as a separate script, so lookups on the column do not
+/// need a scan over the whole table.
+pub struct ReverseIndex<'a> {
+    table_name: &'a str,
+    column: &'a str,
+    entries: &'a ReverseIndexData,
+}
+
+impl<'a> ReverseIndex<'a> {
+    pub fn new(
+        table: &'a TotalWarDbPreProcessed,
+        column: &'a str,
+        entries: &'a ReverseIndexData,
+    ) -> Self {
+        Self {
+            table_name: &table.table_name,
+            column,
+            entries,
+        }
+    }
+
+    /// Path of the reverse index on the given column: the path of the table's script in the reverse index folder, with
+    /// "__by_<column>" appended to the file name (e.g. lua_db/_reverse_indexes/mod/land_units_tables/data____by_category.lua)
+    pub fn path(
+        layout: &OutputLayout,
+        table: &TotalWarDbPreProcessed,
+        column: &str,
+    ) -> Vec<String> {
+        layout.support_file_path(
+            REVERSE_INDEX_FOLDER,
+            &table.script_file_path,
+            &format!("__by_{}", column),
+        )
+    }
+
+    /// Renders the reverse indexes of the table, with their paths
+    pub fn render_all(
+        config: &Config,
+        table: &TotalWarDbPreProcessed,
+    ) -> Vec<(Vec<String>, String)> {
+        table
+            .reverse_indexes
+            .iter()
+            .map(|(column, entries)| {
+                (
+                    Self::path(&config.layout, table, column),
+                    ReverseIndex::new(table, column, entries).to_lua(),
+                )
+            })
+            .collect()
+    }
+
+    pub fn to_lua(&self) -> String {
+        let mut result = String::new();
+        result.push_str(&format!(
+            "-- Reverse index on the {} column of {}\n",
+            self.column, self.table_name
+        ));
+        result.push_str("return {\n");
+        for (value, keys) in self.entries.iter() {
+            result.push_str(&format!("  [{}] = {{ ", value.to_lua_value()));
+            for key in keys.iter() {
+                result.push_str(&format!("{}, ", key.to_lua_value()));
+            }
+            result.push_str("},\n");
+        }
+        result.push('}');
+        result
+    }
+}
@@ -1,12 +1,15 @@
-use crate::config::Config;
+use crate::config::{
+    Config, EmptyOptionalPolicy, MissingPackfilePolicy, PackSettings, ScriptFolder,
+};
 use crate::log::Log;
-use crate::tw_db_pp::{LuaValue, TableData, TotalWarDbPreProcessed};
+use crate::output_writer::find_output_writer;
+use crate::tw_db_pp::{Column, LuaValue, ReverseIndexData, TableData, TotalWarDbPreProcessed};
 use crate::util;
 use crate::wh2_lua_error::Wh2LuaError;
 
 use walkdir::WalkDir;
 
-use std::collections::{BTreeMap, HashMap};
+use std::collections::BTreeMap;
 use std::fs;
 use std::io::{BufReader, Read};
 use std::path::{Path, PathBuf};
@@ -21,7 +24,10 @@ use rpfm_lib::packedfile::PackedFileType;
 use rpfm_lib::packfile::packedfile::{PackedFile, RawPackedFile};
 use rpfm_lib::packfile::{PFHFileType, PFHVersion, PackFile};
 use rpfm_lib::schema;
-use rpfm_lib::schema::Schema;
+use rpfm_lib::schema::{Definition, Field, FieldType, Schema, VersionedFile};
+
+/// Pre-processed db tables per source (packfile name or input directory name), in load order
+pub type PreProcessedSources = Vec<(String, Vec<TotalWarDbPreProcessed>)>;
 
 pub struct Rpfm;
 
@@ -47,12 +53,45 @@ impl Rpfm {
             }
         }
         Log::debug(&format!("Loading schema for {}", game_name));
-        Ok(Schema::load(&rpfm_lib::SUPPORTED_GAMES[game_name].schema)?)
+        let game = rpfm_lib::SUPPORTED_GAMES.get(game_name).ok_or_else(|| {
+            Wh2LuaError::ConfigError(format!("Game not supported by RPFM: {}", game_name))
+        })?;
+        Ok(Schema::load(&game.schema)?)
     }
 
-    pub fn load(
-        config: &Config,
-    ) -> Result<BTreeMap<String, Vec<TotalWarDbPreProcessed>>, Wh2LuaError> {
+    /// Returns the names of all db tables defined in the schema
+    pub fn db_table_names(schema: &Schema) -> Vec<String> {
+        schema
+            .get_ref_versioned_file_db_all()
+            .iter()
+            .filter_map(|versioned_file| match versioned_file {
+                VersionedFile::DB(name, _) => Some(name.to_string()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns the newest definition of the given db table in the schema
+    pub fn latest_db_definition<'a>(
+        schema: &'a Schema,
+        table_name: &str,
+    ) -> Result<&'a Definition, Wh2LuaError> {
+        match schema.get_ref_versioned_file_db(table_name)? {
+            VersionedFile::DB(_, definitions) => definitions
+                .iter()
+                .max_by_key(|definition| definition.get_version())
+                .ok_or_else(|| {
+                    Wh2LuaError::RpfmError(rpfm_error::Error::from(
+                        rpfm_error::ErrorKind::SchemaDefinitionNotFound,
+                    ))
+                }),
+            _ => Err(Wh2LuaError::RpfmError(rpfm_error::Error::from(
+                rpfm_error::ErrorKind::SchemaDefinitionNotFound,
+            ))),
+        }
+    }
+
+    pub fn load(config: &Config) -> Result<PreProcessedSources, Wh2LuaError> {
         Log::debug("Loading files with RPFM...");
 
         let result = if config.packfiles.is_some() && config.packfiles.as_ref().unwrap().len() > 0 {
@@ -63,15 +102,56 @@ impl Rpfm {
             return Err(Wh2LuaError::ConfigError(format!("Neither packfile nor input directory parameters found in config and/or command arguments.")));
         };
 
+        if config.strict {
+            let skipped_tables = Log::skipped_tables();
+            if !skipped_tables.is_empty() {
+                return Err(Wh2LuaError::TablesSkipped(skipped_tables));
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Loads and pre-processes all db tables from a single source: a packfile, or a directory with extracted db files.
+    pub fn load_source(config: &Config, source: &Path) -> Result<PreProcessedSources, Wh2LuaError> {
+        if source.is_dir() {
+            Self::process_in_dir(config, &source.to_path_buf())
+        } else {
+            Self::process_packfiles(config, &[source.to_path_buf()])
+        }
+    }
+
+    /// Reads all files in the script root folder (e.g. "script") of a previously generated packfile.
+    /// Returns the contents per path (relative to the script root folder).
+    pub fn load_generated_packfile_scripts(
+        packfile_path: &Path,
+        script_root: &str,
+    ) -> Result<BTreeMap<Vec<String>, String>, Wh2LuaError> {
+        let mut packfile =
+            PackFile::open_packfiles(&[packfile_path.to_path_buf()], true, false, false)?;
+
+        let script_root: Vec<String> = script_root
+            .split('/')
+            .filter(|e| !e.is_empty())
+            .map(str::to_string)
+            .collect();
+
+        let mut result = BTreeMap::new();
+        for pf in packfile.get_ref_mut_packed_files_by_path_start(&script_root) {
+            let path = pf.get_path()[script_root.len()..].to_vec();
+            let data = pf.get_ref_mut_raw().get_data_and_keep_it()?;
+            result.insert(path, String::from_utf8_lossy(&data).to_string());
+        }
+
         Ok(result)
     }
 
     fn process_packfiles(
         config: &Config,
         packfiles: &[PathBuf],
-    ) -> Result<BTreeMap<String, Vec<TotalWarDbPreProcessed>>, Wh2LuaError> {
+    ) -> Result<PreProcessedSources, Wh2LuaError> {
         Log::debug("Processing packfiles...");
-        let mut result: BTreeMap<String, Vec<TotalWarDbPreProcessed>> = BTreeMap::new();
+        let mut result: PreProcessedSources = Vec::new();
 
         for packfile_path in packfiles {
             Log::info(&format!(
@@ -82,7 +162,26 @@ impl Rpfm {
                     .to_string_lossy()
                     .to_string()
             ));
-            let packfile = PackFile::open_packfiles(&[packfile_path.clone()], true, false, false)?;
+            let packfile =
+                match PackFile::open_packfiles(&[packfile_path.clone()], true, false, false) {
+                    Ok(packfile) => packfile,
+                    Err(error) => {
+                        if config.on_missing == MissingPackfilePolicy::Fail {
+                            return Err(error.into());
+                        }
+                        Log::warning(&format!(
+                            "Could not open packfile {}. The packfile will be skipped.",
+                            packfile_path.display()
+                        ));
+                        Log::warning(&format!("Problem was: {}", error.to_terminal()));
+                        Log::add_skipped_packfile(format!(
+                            "{} ({})",
+                            packfile_path.display(),
+                            error.to_terminal()
+                        ));
+                        continue;
+                    }
+                };
 
             let mut pf_processed_result: Vec<TotalWarDbPreProcessed> = Vec::new();
 
@@ -122,6 +221,15 @@ impl Rpfm {
                         ));
                         Log::warning(&format!("Problem was: {}", decode_result.err().unwrap()));
                         Log::warning(&format!("(If the mod this table belongs to actually works and doesn't crash the game, the table likely does nothing and this is unlikely to cause any problems)"));
+                        Log::add_skipped_table(format!(
+                            "{} - {}: could not be decoded",
+                            packfile_path
+                                .file_name()
+                                .unwrap()
+                                .to_string_lossy()
+                                .to_string(),
+                            pf.get_path().join("/")
+                        ));
 
                         #[cfg(not(debug_assertions))]
                         Log::set_single_line_log(true);
@@ -138,14 +246,17 @@ impl Rpfm {
                         Some(packfile_path),
                     )?;
 
-                    pf_processed_result.push(Self::convert_rpfm_db_to_preprocessed_db(
+                    let mut table = Self::convert_rpfm_db_to_preprocessed_db(
+                        config,
                         &db,
                         db.get_ref_table_name(),
                         script_file_path,
-                    )?);
+                    )?;
+                    table.folder = Self::script_folder(config, &pf_file_name);
+                    pf_processed_result.push(table);
                 }
 
-                result.insert(
+                result.push((
                     packfile_path
                         .file_stem()
                         .unwrap()
@@ -153,7 +264,7 @@ impl Rpfm {
                         .unwrap()
                         .to_string(),
                     pf_processed_result,
-                );
+                ));
 
                 Log::rpfm(&format!(
                     "Processing db files for {} - DONE",
@@ -171,6 +282,85 @@ impl Rpfm {
         Ok(result)
     }
 
+    /// Decodes a single db table for inspection, either from all matching db files in a packfile, or from a single
+    /// extracted db file (in which case the table name is taken from its parent folder if not given).
+    ///
+    /// The script file path of each resulting table is simply [<table_name>, <db_file_name>].
+    pub fn load_single_table(
+        config: &Config,
+        source: &Path,
+        table_name: Option<&str>,
+    ) -> Result<Vec<TotalWarDbPreProcessed>, Wh2LuaError> {
+        if !source.exists() {
+            return Err(Wh2LuaError::ConfigError(format!(
+                "File with specified path not found: {}",
+                source.display()
+            )));
+        }
+
+        let mut result = Vec::new();
+
+        if source
+            .extension()
+            .map_or(false, |extension| extension == "pack")
+        {
+            let table_name = table_name.ok_or_else(|| {
+                Wh2LuaError::ConfigError(
+                    "A table name is required to inspect a table in a packfile".to_string(),
+                )
+            })?;
+
+            let packfile = PackFile::open_packfiles(&[source.to_path_buf()], true, false, false)?;
+            let mut packed_db_files = packfile.get_packed_files_by_type(PackedFileType::DB, true);
+
+            for pf in packed_db_files.iter_mut() {
+                if pf.get_path().get(1).map(String::as_str) != Some(table_name) {
+                    continue;
+                }
+                let pf_file_name = pf.get_path().last().unwrap().clone();
+                let db = Self::decode_db_packed_file(pf.get_ref_mut_raw(), &config.schema)?;
+                result.push(Self::convert_rpfm_db_to_preprocessed_db(
+                    config,
+                    &db,
+                    db.get_ref_table_name(),
+                    vec![table_name.to_string(), pf_file_name],
+                )?);
+            }
+        } else {
+            let table_name = match table_name {
+                Some(table_name) => table_name,
+                None => util::get_parent_folder_name(source)?,
+            };
+
+            let mut data = vec![];
+            {
+                let mut file = BufReader::new(fs::File::open(source)?);
+                file.read_to_end(&mut data)?;
+            }
+
+            let db = DB::read(&data, table_name, &config.schema, false)?;
+            result.push(Self::convert_rpfm_db_to_preprocessed_db(
+                config,
+                &db,
+                db.get_ref_table_name(),
+                vec![
+                    table_name.to_string(),
+                    source.file_name().unwrap().to_string_lossy().to_string(),
+                ],
+            )?);
+        }
+
+        if result.is_empty() {
+            return Err(Wh2LuaError::ConfigError(format!(
+                "No db files for table {} found in {}",
+                table_name.unwrap_or_default(),
+                source.display()
+            )));
+        }
+
+        Ok(result)
+    }
+
     fn decode_db_packed_file(
         raw_packed_file: &mut RawPackedFile,
         schema: &Schema,
@@ -194,48 +384,60 @@ impl Rpfm {
         }
     }
 
+    fn script_folder(config: &Config, db_file_name: &str) -> ScriptFolder {
+        if db_file_name != "data__" {
+            ScriptFolder::Mod
+        } else if config.base_mod {
+            ScriptFolder::Core
+        } else {
+            ScriptFolder::ModCore
+        }
+    }
+
     fn create_script_file_path(
         config: &Config,
         db_table: &str,
         db_file_name: &str,
         packfile_path: Option<&PathBuf>,
     ) -> Result<Vec<String>, Wh2LuaError> {
-        let mut file_name_without_extension = db_file_name.to_string();
-        let mut table_folder = "mod".to_string();
+        let folder = Self::script_folder(config, db_file_name);
+
+        let pack_name = match packfile_path {
+            Some(file_path) => file_path.file_stem().unwrap().to_str().unwrap().to_string(),
+            None => config
+                .in_dir
+                .as_ref()
+                .and_then(|in_dir| in_dir.file_name())
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+        };
 
-        if file_name_without_extension == "data__" {
-            if config.base_mod {
-                table_folder = "core".to_string();
+        let mut file_name_without_extension = db_file_name.to_string();
+        if folder == ScriptFolder::ModCore {
+            if let Some(core_prefix) = &config.mod_core_prefix {
+                file_name_without_extension =
+                    format!("{}_{}", core_prefix, &file_name_without_extension);
+            } else if packfile_path.is_some() {
+                file_name_without_extension =
+                    format!("{}_{}", pack_name, &file_name_without_extension);
             } else {
-                table_folder = "mod_core".to_string();
-                if let Some(core_prefix) = &config.mod_core_prefix {
-                    file_name_without_extension =
-                        format!("{}_{}", core_prefix, &file_name_without_extension);
-                } else if let Some(file_path) = packfile_path {
-                    file_name_without_extension = format!(
-                        "{}_{}",
-                        file_path.file_stem().unwrap().to_str().unwrap(),
-                        &file_name_without_extension
-                    );
-                } else {
-                    return Err(Wh2LuaError::ConfigError(format!("A (core) data__ file was found in the input files, but the --base flag is not set,\n  and no --core-prefix or --packfile was specified.\n  No sensible output filename could be determined.")));
-                }
+                return Err(Wh2LuaError::ConfigError(format!("A (core) data__ file was found in the input files, but the --base flag is not set,\n  and no --core-prefix or --packfile was specified.\n  No sensible output filename could be determined.")));
             }
         }
 
-        let mut output_file_path = Vec::new();
-        output_file_path.push("lua_db".to_string());
-        output_file_path.push(table_folder);
-        output_file_path.push(db_table.to_string());
-        output_file_path.push(format!("{}.lua", file_name_without_extension));
-
-        Ok(output_file_path)
+        Ok(config.layout.script_path(
+            folder,
+            db_table,
+            &pack_name,
+            &file_name_without_extension,
+            find_output_writer(&config.format)?.file_extension(),
+        ))
     }
 
     fn process_in_dir(
         config: &Config,
         in_dir: &PathBuf,
-    ) -> Result<BTreeMap<String, Vec<TotalWarDbPreProcessed>>, Wh2LuaError> {
+    ) -> Result<PreProcessedSources, Wh2LuaError> {
         Log::debug(&format!(
             "Processing extracted db files in input directory: {}",
             in_dir.display()
@@ -249,43 +451,64 @@ impl Rpfm {
 
         let in_dir_name = in_dir.file_name().unwrap().to_string_lossy().to_string();
 
-        let rpfm_in_dir: PathBuf = [in_dir.as_path(), Path::new("db")].iter().collect();
-
         let mut dir_result: Vec<TotalWarDbPreProcessed> = Vec::new();
 
         #[cfg(not(debug_assertions))]
         Log::set_single_line_log(true);
 
-        for entry in WalkDir::new(rpfm_in_dir.as_path()).min_depth(2) {
-            let entry = entry.unwrap();
-            if entry.path().extension().is_none() {
-                let relative_path = util::strip_db_prefix_from_path(&entry.path());
-                let db_table = util::get_parent_folder_name(&relative_path)?;
+        for db_file in Self::find_in_dir_db_files(in_dir) {
+            dir_result.push(Self::pre_process_in_dir_file(config, &db_file)?);
+        }
 
-                let db_file_name = entry.path().file_stem().unwrap().to_str().unwrap();
+        Log::rpfm("Processing files - DONE");
 
-                let script_file_path =
-                    Self::create_script_file_path(config, db_table, db_file_name, None)?;
+        Log::set_single_line_log(false);
 
-                Log::rpfm(&format!("Processing file: {}", relative_path.display()));
+        Ok(vec![(in_dir_name, dir_result)])
+    }
 
-                dir_result.push(Self::pre_process_db_file(
-                    &config,
-                    &entry.path(),
-                    script_file_path,
-                )?);
-            }
-        }
+    /// Returns the paths of all extracted db files (<in_dir>/db/<table>/<db_file>) in the input directory
+    pub fn find_in_dir_db_files(in_dir: &Path) -> Vec<PathBuf> {
+        let rpfm_in_dir: PathBuf = [in_dir, Path::new("db")].iter().collect();
+
+        WalkDir::new(rpfm_in_dir.as_path())
+            .min_depth(2)
+            .into_iter()
+            // Files can be saved or removed while the directory is walked (e.g. in watch mode), so entries that cannot be
+            // read are left out, to be picked up by the next walk
+            .filter_map(|entry| match entry {
+                Ok(entry) => Some(entry),
+                Err(error) => {
+                    Log::debug(&format!(
+                        "Skipping unreadable input directory entry: {}",
+                        error
+                    ));
+                    None
+                }
+            })
+            .filter(|entry| entry.path().extension().is_none())
+            .map(|entry| entry.path().to_path_buf())
+            .collect()
+    }
 
-        Log::rpfm("Processing files - DONE");
+    /// Pre-processes a single extracted db file from an input directory, with the script file path based on its table
+    /// and file name
+    pub fn pre_process_in_dir_file(
+        config: &Config,
+        db_file: &Path,
+    ) -> Result<TotalWarDbPreProcessed, Wh2LuaError> {
+        let relative_path = util::strip_db_prefix_from_path(db_file);
+        let db_table = util::get_parent_folder_name(&relative_path)?;
 
-        Log::set_single_line_log(false);
+        let db_file_name = db_file.file_stem().unwrap().to_str().unwrap();
 
-        let mut result: BTreeMap<String, Vec<TotalWarDbPreProcessed>> = BTreeMap::new();
+        let script_file_path = Self::create_script_file_path(config, db_table, db_file_name, None)?;
 
-        result.insert(in_dir_name, dir_result);
+        Log::rpfm(&format!("Processing file: {}", relative_path.display()));
 
-        Ok(result)
+        let mut table = Self::pre_process_db_file(config, db_file, script_file_path)?;
+        table.folder = Self::script_folder(config, db_file_name);
+        Ok(table)
     }
 
     pub fn pre_process_db_file(
@@ -318,12 +541,18 @@ impl Rpfm {
                     "RPFM could not load table {} due to a missing definition in the schema, returning empty table",
                     table_name
                 ));
+                Log::add_skipped_table(format!(
+                    "{}: missing definition in the schema",
+                    rpfm_db_file.display()
+                ));
                 #[cfg(not(debug_assertions))]
                 Log::set_single_line_log(true);
 
                 Ok(TotalWarDbPreProcessed::new(
                     table_name,
                     TableData::FlatArray(vec![vec![]]),
+                    Vec::new(),
+                    None,
                     script_file_path,
                 ))
             } else {
@@ -332,6 +561,7 @@ impl Rpfm {
         } else {
             let db = db_result.unwrap();
             Ok(Self::convert_rpfm_db_to_preprocessed_db(
+                config,
                 &db,
                 db.get_ref_table_name(),
                 script_file_path,
@@ -339,7 +569,8 @@ impl Rpfm {
         }
     }
 
-    fn convert_rpfm_db_to_preprocessed_db(
+    pub fn convert_rpfm_db_to_preprocessed_db(
+        config: &Config,
         rpfm_db: &DB,
         table_name: &str,
         script_file_path: Vec<String>,
@@ -356,6 +587,14 @@ impl Rpfm {
             .count()
             == 1;
 
+        // Built from the values before fields are left out of the rows, so every row is indexed
+        let indexed_columns =
+            Self::indexed_columns(config, table_name, &rpfm_fields, &script_file_path)?;
+        let mut reverse_indexes: BTreeMap<String, ReverseIndexData> = indexed_columns
+            .iter()
+            .map(|(_, column)| (column.clone(), BTreeMap::new()))
+            .collect();
+
         let data = if is_single_key {
             let key_field_index = rpfm_fields
                 .iter()
@@ -363,39 +602,216 @@ impl Rpfm {
                 .unwrap();
 
             let mut processed_data: BTreeMap<LuaValue, Vec<(LuaValue, LuaValue)>> = BTreeMap::new();
+            // Key -> the values of the indexed columns. Like the rows, a later row with the same key replaces an
+            // earlier one.
+            let mut indexed_values: BTreeMap<LuaValue, Vec<LuaValue>> = BTreeMap::new();
 
             for row in rpfm_data {
                 let key_data = Self::decoded_data_to_lua_value(&row[key_field_index]);
                 processed_data.insert(key_data.clone(), Vec::new());
+                indexed_values.insert(
+                    key_data.clone(),
+                    indexed_columns
+                        .iter()
+                        .map(|(index, _)| {
+                            Self::indexed_value(config, &rpfm_fields[*index], &row[*index])
+                        })
+                        .collect(),
+                );
                 for (field, data) in rpfm_fields.iter().zip(row.iter()) {
-                    processed_data.get_mut(&key_data).unwrap().push((
-                        LuaValue::Text(field.get_name().to_string()),
-                        Self::decoded_data_to_lua_value(data),
-                    ));
+                    if let Some(value) = Self::field_to_lua_value(config, field, data) {
+                        processed_data
+                            .get_mut(&key_data)
+                            .unwrap()
+                            .push((LuaValue::Text(field.get_name().to_string()), value));
+                    }
+                }
+            }
+
+            for (key, values) in indexed_values {
+                for ((_, column), value) in indexed_columns.iter().zip(values) {
+                    let reverse_index = reverse_indexes.get_mut(column).unwrap();
+                    reverse_index.entry(value).or_default().push(key.clone());
                 }
             }
 
             TableData::KeyValue(processed_data)
         } else {
             let mut processed_data: Vec<Vec<(LuaValue, LuaValue)>> = Vec::new();
-            for row in rpfm_data {
+            for (row_index, row) in rpfm_data.iter().enumerate() {
+                for (index, column) in indexed_columns.iter() {
+                    let value = Self::indexed_value(config, &rpfm_fields[*index], &row[*index]);
+                    let reverse_index = reverse_indexes.get_mut(column).unwrap();
+                    reverse_index
+                        .entry(value)
+                        .or_default()
+                        .push(LuaValue::Number((row_index + 1).to_string()));
+                }
+
                 let mut processed_row: Vec<(LuaValue, LuaValue)> = Vec::new();
                 for (field, data) in rpfm_fields.iter().zip(row.iter()) {
-                    processed_row.push((
-                        LuaValue::Text(field.get_name().to_string()),
-                        Self::decoded_data_to_lua_value(data),
-                    ));
+                    if let Some(value) = Self::field_to_lua_value(config, field, data) {
+                        processed_row.push((LuaValue::Text(field.get_name().to_string()), value));
+                    }
                 }
                 processed_data.push(processed_row);
             }
             TableData::FlatArray(processed_data)
         };
 
-        Ok(TotalWarDbPreProcessed::new(
+        let key_columns = rpfm_fields
+            .iter()
+            .filter(|field| field.get_is_key())
+            .map(|field| field.get_name().to_string())
+            .collect();
+
+        let mut table = TotalWarDbPreProcessed::new(
             table_name,
             data,
+            key_columns,
+            Some(rpfm_db.get_ref_definition().get_version()),
             script_file_path,
-        ))
+        );
+        table.columns = rpfm_fields
+            .iter()
+            .map(|field| Self::field_to_column(config, field))
+            .collect();
+        table.reverse_indexes = reverse_indexes;
+        Ok(table)
+    }
+
+    /// Returns the columns of the table to build reverse indexes on (see `Config::reverse_indexes`), with their
+    /// position in the row.
+    ///
+    /// A column that is missing from the newest definition of the table in the schema is an error. A column that is
+    /// only missing from the (older) version of this db file is skipped with a warning, like other problems with a
+    /// single table (and is therefore an error in strict mode).
+    fn indexed_columns(
+        config: &Config,
+        table_name: &str,
+        fields: &[Field],
+        script_file_path: &[String],
+    ) -> Result<Vec<(usize, String)>, Wh2LuaError> {
+        let columns = match config.reverse_indexes.get(table_name) {
+            Some(columns) => columns,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut result = Vec::new();
+        for column in columns.iter() {
+            if let Some(index) = fields.iter().position(|field| field.get_name() == column) {
+                result.push((index, column.clone()));
+                continue;
+            }
+
+            let latest_definition = Self::latest_db_definition(&config.schema, table_name)?;
+            if !latest_definition
+                .get_fields_processed()
+                .iter()
+                .any(|field| field.get_name() == column)
+            {
+                return Err(Wh2LuaError::ConfigError(format!(
+                    "Cannot create reverse index: table {} has no column {}",
+                    table_name, column
+                )));
+            }
+
+            Log::set_single_line_log(false);
+            Log::warning(&format!(
+                "Column {} is missing from this version of table {}, skipping its reverse index for {}",
+                column,
+                table_name,
+                script_file_path.join("/")
+            ));
+            Log::add_skipped_table(format!(
+                "{} - {}: no column {} for the reverse index (older table version)",
+                table_name,
+                script_file_path.join("/"),
+                column
+            ));
+        }
+        Ok(result)
+    }
+
+    /// The value of a field as used in a reverse index: the value as written in the row, or the decoded value if the
+    /// field is left out of the row (see `field_to_lua_value`)
+    fn indexed_value(config: &Config, field: &Field, data: &DecodedData) -> LuaValue {
+        Self::field_to_lua_value(config, field, data)
+            .unwrap_or_else(|| Self::decoded_data_to_lua_value(data))
+    }
+
+    fn field_to_column(config: &Config, field: &Field) -> Column {
+        let (lua_type, is_optional_string) = match field.get_ref_field_type() {
+            FieldType::Boolean => ("boolean", false),
+            FieldType::F32 => ("number", false),
+            FieldType::I16 | FieldType::I32 | FieldType::I64 => ("integer", false),
+            FieldType::StringU8 | FieldType::StringU16 => ("string", false),
+            FieldType::OptionalStringU8 | FieldType::OptionalStringU16 => ("string", true),
+            // Sequences are written as their type name (see decoded_data_to_lua_value)
+            FieldType::SequenceU16(_) | FieldType::SequenceU32(_) => ("string", false),
+        };
+        let nillable = !field.get_is_key()
+            && ((config.omit_defaults && field.get_default_value().is_some())
+                || (is_optional_string && config.empty_optional == EmptyOptionalPolicy::Nil));
+        Column {
+            name: field.get_name().to_string(),
+            lua_type,
+            nillable,
+        }
+    }
+
+    /// Converts the value of a field in a row, or returns None if the field should be left out of the row (which makes
+    /// it nil in Lua). Key fields are never left out.
+    fn field_to_lua_value(config: &Config, field: &Field, data: &DecodedData) -> Option<LuaValue> {
+        if !field.get_is_key() {
+            if config.omit_defaults && Self::is_default_value(field, data) {
+                return None;
+            }
+            match data {
+                DecodedData::OptionalStringU8(value) | DecodedData::OptionalStringU16(value)
+                    if value.is_empty() =>
+                {
+                    return match &config.empty_optional {
+                        EmptyOptionalPolicy::Empty => Some(LuaValue::Text(String::new())),
+                        EmptyOptionalPolicy::Nil => None,
+                        EmptyOptionalPolicy::Sentinel(sentinel) => {
+                            Some(LuaValue::Text(sentinel.clone()))
+                        }
+                    };
+                }
+                _ => {}
+            }
+        }
+        Some(Self::decoded_data_to_lua_value(data))
+    }
+
+    /// Returns true if the value equals the default value of the field in the schema
+    fn is_default_value(field: &Field, data: &DecodedData) -> bool {
+        let default_value = field.get_default_value();
+        let default_value = match default_value.as_deref() {
+            Some(default_value) => default_value,
+            None => return false,
+        };
+        match data {
+            DecodedData::Boolean(value) => match default_value.to_lowercase().as_str() {
+                "true" | "1" => *value,
+                "false" | "0" => !*value,
+                _ => false,
+            },
+            DecodedData::F32(value) => {
+                default_value.parse::<f32>().map_or(false, |default_value| {
+                    (default_value - *value).abs() < f32::EPSILON
+                })
+            }
+            DecodedData::I16(value) => default_value.parse::<i16>() == Ok(*value),
+            DecodedData::I32(value) => default_value.parse::<i32>() == Ok(*value),
+            DecodedData::I64(value) => default_value.parse::<i64>() == Ok(*value),
+            DecodedData::StringU8(value)
+            | DecodedData::StringU16(value)
+            | DecodedData::OptionalStringU8(value)
+            | DecodedData::OptionalStringU16(value) => value == default_value,
+            DecodedData::SequenceU16(_) | DecodedData::SequenceU32(_) => false,
+        }
     }
 
     fn decoded_data_to_lua_value(data: &DecodedData) -> LuaValue {
@@ -414,13 +830,20 @@ impl Rpfm {
         }
     }
 
+    /// Creates a packfile containing the given scripts, under the given script root folder (e.g. "script")
     pub fn generate_packfile_with_script(
-        scripts_to_pack: HashMap<Vec<String>, (String, String)>,
+        scripts_to_pack: &BTreeMap<Vec<String>, String>,
+        pack_settings: &PackSettings,
+        script_root: &str,
     ) -> Result<PackFile, Wh2LuaError> {
-        let mut packfile = PackFile::new_with_name("lua_db_generated.pack", PFHVersion::PFH5);
-        packfile.set_pfh_file_type(PFHFileType::Movie);
+        let mut packfile = PackFile::new_with_name(&pack_settings.name, pack_settings.version);
+        packfile.set_pfh_file_type(pack_settings.file_type);
         for (path, value) in scripts_to_pack.iter() {
-            let mut script_path = vec!["script".to_string()];
+            let mut script_path: Vec<String> = script_root
+                .split('/')
+                .filter(|e| !e.is_empty())
+                .map(str::to_string)
+                .collect();
             path.iter().for_each(|e| script_path.push(e.clone()));
             let timestamp = SystemTime::now()
                 .duration_since(UNIX_EPOCH)
@@ -429,10 +852,10 @@ impl Rpfm {
 
             let raw_packed_file = RawPackedFile::read_from_vec(
                 script_path,
-                "lua_db_generated.pack".to_string(),
+                pack_settings.name.clone(),
                 timestamp,
                 false,
-                value.1.as_bytes().to_vec(),
+                value.as_bytes().to_vec(),
             );
             packfile.add_packed_file(&PackedFile::new_from_raw(&raw_packed_file), true)?;
         }
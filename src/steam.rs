@@ -0,0 +1,248 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::log::Log;
+use crate::wh2_lua_error::Wh2LuaError;
+
+/// Resolves packfile names (as listed in a KMM profile) to actual packfile paths.
+///
+/// The game's data folder is checked first, then the workshop content folders of all known Steam libraries.
+pub struct PackfileResolver {
+    data_dir: PathBuf,
+    workshop_item_dirs: Vec<PathBuf>,
+}
+
+impl PackfileResolver {
+    /// Creates a resolver for the game with the given Steam app id, which is also the name of its workshop content folder
+    pub fn new(data_dir: PathBuf, library_roots: &[PathBuf], steam_app_id: &str) -> Self {
+        let mut workshop_item_dirs = Vec::new();
+
+        for library_root in library_roots {
+            let content_dir: PathBuf = [
+                library_root.as_path(),
+                Path::new("steamapps"),
+                Path::new("workshop"),
+                Path::new("content"),
+                Path::new(steam_app_id),
+            ]
+            .iter()
+            .collect();
+
+            let entries = match fs::read_dir(&content_dir) {
+                Ok(entries) => entries,
+                Err(_) => {
+                    Log::debug(&format!(
+                        "No workshop content folder found at {}",
+                        content_dir.display()
+                    ));
+                    continue;
+                }
+            };
+
+            Log::debug(&format!(
+                "Looking for workshop packfiles in {}",
+                content_dir.display()
+            ));
+            for entry in entries.filter_map(Result::ok) {
+                if entry.path().is_dir() {
+                    workshop_item_dirs.push(entry.path());
+                }
+            }
+        }
+
+        Self {
+            data_dir,
+            workshop_item_dirs,
+        }
+    }
+
+    /// Returns the path of the packfile with the given name, or None if it cannot be found anywhere.
+    pub fn resolve(&self, packfile_name: &str) -> Option<PathBuf> {
+        let data_path = self.data_dir.join(packfile_name);
+        if data_path.is_file() {
+            return Some(data_path);
+        }
+
+        self.workshop_item_dirs
+            .iter()
+            .map(|workshop_item_dir| workshop_item_dir.join(packfile_name))
+            .find(|path| path.is_file())
+    }
+}
+
+/// Collects the root folders of all known Steam libraries.
+///
+/// These are the Steam install directory (if known), any extra libraries that were passed explicitly, the library
+/// the current directory belongs to (if we are running from the game's install directory), and all libraries listed
+/// in the libraryfolders.vdf files of those.
+pub fn find_library_roots(steam_dir: Option<&Path>, extra_libraries: &[PathBuf]) -> Vec<PathBuf> {
+    let mut library_roots: Vec<PathBuf> = Vec::new();
+
+    if let Some(steam_dir) = steam_dir {
+        library_roots.push(steam_dir.to_path_buf());
+    }
+
+    library_roots.extend(extra_libraries.iter().cloned());
+
+    if let Some(current_library) = current_dir_library_root() {
+        library_roots.push(current_library);
+    }
+
+    let mut listed_libraries: Vec<PathBuf> = Vec::new();
+    for library_root in &library_roots {
+        let library_folders_vdf: PathBuf = [
+            library_root.as_path(),
+            Path::new("steamapps"),
+            Path::new("libraryfolders.vdf"),
+        ]
+        .iter()
+        .collect();
+
+        if library_folders_vdf.is_file() {
+            match parse_library_folders_vdf(&library_folders_vdf) {
+                Ok(libraries) => listed_libraries.extend(libraries),
+                Err(error) => Log::warning(&format!(
+                    "Could not read Steam library folders from {}: {}",
+                    library_folders_vdf.display(),
+                    error
+                )),
+            }
+        }
+    }
+
+    for library in listed_libraries {
+        if !library_roots.contains(&library) {
+            library_roots.push(library);
+        }
+    }
+
+    library_roots
+}
+
+/// If the current directory is a game install directory (<library>/steamapps/common/<game>), returns <library>.
+fn current_dir_library_root() -> Option<PathBuf> {
+    let current_dir = std::env::current_dir().ok()?;
+    let common_dir = current_dir.parent()?;
+    let steamapps_dir = common_dir.parent()?;
+
+    if common_dir.file_name()? == "common" && steamapps_dir.file_name()? == "steamapps" {
+        steamapps_dir.parent().map(Path::to_path_buf)
+    } else {
+        None
+    }
+}
+
+/// Parses Steam's libraryfolders.vdf and returns the library paths listed in it.
+pub fn parse_library_folders_vdf(path: &Path) -> Result<Vec<PathBuf>, Wh2LuaError> {
+    Ok(parse_library_folders(&fs::read_to_string(path)?))
+}
+
+/// Returns the library paths listed in the contents of a libraryfolders.vdf file.
+///
+/// Older versions of the file list libraries as "<number>" <tab> "Path\\To\\SteamLibrary" directly in the root block,
+/// newer versions as a numbered block in the root block, with "path" <tab> "Path\\To\\SteamLibrary" in it (next to e.g.
+/// an "apps" block, with app ids and sizes). Both are supported.
+fn parse_library_folders(contents: &str) -> Vec<PathBuf> {
+    let mut libraries = Vec::new();
+
+    // The keys of the blocks the current line is in, starting with the root block
+    let mut blocks: Vec<&str> = Vec::new();
+    // The last key without a value, which names the block that follows it
+    let mut block_key = "";
+
+    for line in contents.lines() {
+        let line = line.trim();
+        if line == "{" {
+            blocks.push(block_key);
+            continue;
+        } else if line == "}" {
+            blocks.pop();
+            continue;
+        }
+
+        let tokens: Vec<&str> = line.split('"').collect();
+        // A key line looks like: "" key "", a key-value line like: "" key "<whitespace>" value ""
+        if tokens.len() < 2 {
+            continue;
+        }
+        let key = tokens[1];
+        if tokens.len() < 4 {
+            block_key = key;
+            continue;
+        }
+
+        let is_library = match blocks.as_slice() {
+            // Old format
+            [_root] => key.parse::<u32>().is_ok(),
+            // New format
+            [_root, library] => key == "path" && library.parse::<u32>().is_ok(),
+            _ => false,
+        };
+        if is_library {
+            libraries.push(PathBuf::from(tokens[3].replace("\\\\", "\\")));
+        }
+    }
+
+    libraries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_old_library_folders_format() {
+        let contents = r#""LibraryFolders"
+{
+	"TimeNextStatsReport"		"1612345678"
+	"ContentStatsID"		"-1234567890123456789"
+	"1"		"D:\\SteamLibrary"
+	"2"		"E:\\Games\\Steam"
+}
+"#;
+        assert_eq!(
+            parse_library_folders(contents),
+            vec![
+                PathBuf::from("D:\\SteamLibrary"),
+                PathBuf::from("E:\\Games\\Steam")
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_new_library_folders_format() {
+        let contents = r#""libraryfolders"
+{
+	"contentstatsid"		"-1234567890123456789"
+	"0"
+	{
+		"path"		"C:\\Program Files (x86)\\Steam"
+		"label"		""
+		"contentid"		"1234567890"
+		"totalsize"		"0"
+		"apps"
+		{
+			"228980"		"400000000"
+		}
+	}
+	"1"
+	{
+		"path"		"D:\\SteamLibrary"
+		"label"		""
+		"apps"
+		{
+			"594570"		"60000000000"
+			"1"		"12345"
+		}
+	}
+}
+"#;
+        assert_eq!(
+            parse_library_folders(contents),
+            vec![
+                PathBuf::from("C:\\Program Files (x86)\\Steam"),
+                PathBuf::from("D:\\SteamLibrary")
+            ]
+        );
+    }
+}
@@ -1,7 +1,7 @@
 use std::collections::BTreeMap;
 use std::path::PathBuf;
 
-use crate::config::Config;
+use crate::config::{Config, ScriptFolder};
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum LuaValue {
@@ -18,8 +18,30 @@ impl LuaValue {
             &LuaValue::Text(value) => format!("\"{}\"", value),
         }
     }
+
+    /// Returns the value as plain text, without quotes
+    pub fn to_plain_string(&self) -> String {
+        match &self {
+            &LuaValue::Boolean(value) => format!("{}", value),
+            &LuaValue::Number(value) | &LuaValue::Text(value) => value.clone(),
+        }
+    }
+}
+
+/// A column of a db table, as defined in the schema
+#[derive(Debug, Clone)]
+pub struct Column {
+    pub name: String,
+    /// The Lua type of the values in the column (e.g. "string" or "integer")
+    pub lua_type: &'static str,
+    /// Whether the field can be left out of a row (see `Config::empty_optional` and `Config::omit_defaults`)
+    pub nillable: bool,
 }
 
+/// A reverse index on a column: column value -> the keys of all rows with that value (or the row numbers, for tables
+/// that are generated as an array)
+pub type ReverseIndexData = BTreeMap<LuaValue, Vec<LuaValue>>;
+
 pub enum TableData {
     KeyValue(BTreeMap<LuaValue, Vec<(LuaValue, LuaValue)>>),
     FlatArray(Vec<Vec<(LuaValue, LuaValue)>>),
@@ -29,14 +51,79 @@ pub struct TotalWarDbPreProcessed {
     pub table_name: String,
     pub script_file_path: Vec<String>,
     pub data: TableData,
+    /// The names of the key columns of the table, in schema order
+    pub key_columns: Vec<String>,
+    /// The version of the schema definition the table was decoded with, if any
+    pub version: Option<i32>,
+    /// The folder the script for this table is placed in (see `OutputLayout`)
+    pub folder: ScriptFolder,
+    /// The columns of the table, in schema order (empty if the table has no definition in the schema)
+    pub columns: Vec<Column>,
+    /// Column -> the reverse index on that column, for the columns configured in `Config::reverse_indexes`
+    pub reverse_indexes: BTreeMap<String, ReverseIndexData>,
 }
 
 impl TotalWarDbPreProcessed {
-    pub fn new(table_name: &str, data: TableData, script_file_path: Vec<String>) -> Self {
+    pub fn new(
+        table_name: &str,
+        data: TableData,
+        key_columns: Vec<String>,
+        version: Option<i32>,
+        script_file_path: Vec<String>,
+    ) -> Self {
         Self {
             table_name: table_name.to_string(),
             script_file_path,
             data,
+            key_columns,
+            version,
+            folder: ScriptFolder::Mod,
+            columns: Vec::new(),
+            reverse_indexes: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the number of (non-empty) rows in the table
+    pub fn row_count(&self) -> usize {
+        match &self.data {
+            TableData::KeyValue(kv_table_data) => kv_table_data.len(),
+            TableData::FlatArray(arr_table_data) => {
+                arr_table_data.iter().filter(|row| !row.is_empty()).count()
+            }
+        }
+    }
+
+    /// Returns all rows of the table, each with a key identifying the row.
+    ///
+    /// For tables with a single key column, this is the value of that column. Otherwise, it is the values of all key
+    /// columns joined with "|" (or the row number if the table has no key columns).
+    pub fn keyed_rows(&self) -> Vec<(String, &Vec<(LuaValue, LuaValue)>)> {
+        match &self.data {
+            TableData::KeyValue(kv_table_data) => kv_table_data
+                .iter()
+                .map(|(key, row)| (key.to_plain_string(), row))
+                .collect(),
+            TableData::FlatArray(arr_table_data) => arr_table_data
+                .iter()
+                .enumerate()
+                .map(|(index, row)| {
+                    let key = if self.key_columns.is_empty() {
+                        (index + 1).to_string()
+                    } else {
+                        self.key_columns
+                            .iter()
+                            .map(|key_column| {
+                                row.iter()
+                                    .find(|(k, _)| &k.to_plain_string() == key_column)
+                                    .map(|(_, v)| v.to_plain_string())
+                                    .unwrap_or_default()
+                            })
+                            .collect::<Vec<_>>()
+                            .join("|")
+                    };
+                    (key, row)
+                })
+                .collect(),
         }
     }
 
@@ -0,0 +1,298 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::config::{Config, MissingPackfilePolicy, OutputSinkKind, OverwritePolicy};
+use crate::generated_files;
+use crate::log::Log;
+use crate::output_sink::create_output_sink;
+use crate::output_summary::OutputSummary;
+use crate::output_writer::{find_output_writer, OutputWriter};
+use crate::rpfm::Rpfm;
+use crate::tw_db_pp::TotalWarDbPreProcessed;
+use crate::wh2_lua_error::Wh2LuaError;
+
+/// How often the watched files are checked for changes
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A watched packfile or extracted db file, with the tables decoded from it
+struct WatchedFile {
+    modified: Option<SystemTime>,
+    tables: Vec<RenderedTable>,
+    /// The tables that were skipped when the file was last processed successfully
+    skipped_tables: Vec<String>,
+}
+
+/// A decoded db table, with the files generated for it
+struct RenderedTable {
+    /// The packfile (or input directory) the table came from
+    source: String,
+    table: TotalWarDbPreProcessed,
+    files: Vec<(Vec<String>, String)>,
+}
+
+/// Generates the output once, then keeps watching the packfiles or the extracted db files in the input directory,
+/// and regenerates the scripts for only the changed files. Runs until the process is stopped, or an unrecoverable
+/// error occurs.
+///
+/// Unpacked (and stdout) output only gets the changed scripts written. Packfile and zip output is always saved as a
+/// whole, but only the scripts of changed files are re-rendered.
+pub fn watch(config: &Config) -> Result<(), Wh2LuaError> {
+    let writer = find_output_writer(&config.format)?;
+
+    crate::prepare_output_dir(config)?;
+
+    // After the initial run, a file that cannot be opened, or in which a table cannot be decoded, is most likely still
+    // being saved. Treat this as an error, so the file's previous output is kept.
+    let mut changed_file_config = config.clone();
+    changed_file_config.on_missing = MissingPackfilePolicy::Fail;
+    changed_file_config.strict = true;
+
+    let mut watched_files: BTreeMap<PathBuf, WatchedFile> = BTreeMap::new();
+    let mut written_scripts: BTreeMap<Vec<String>, String> = BTreeMap::new();
+    let mut initial = true;
+
+    loop {
+        let render_config = if initial {
+            config
+        } else {
+            &changed_file_config
+        };
+        if update_watched_files(render_config, writer.as_ref(), &mut watched_files, initial)? {
+            let scripts = merge_scripts(config, &watched_files)?;
+            write_changed_scripts(config, &written_scripts, &scripts)?;
+            written_scripts = scripts;
+
+            Log::info("Watching for changes... (press Ctrl+C to stop)");
+        }
+        initial = false;
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Re-processes all watched files that were added or modified since the last check, and forgets removed files.
+/// Returns true if anything changed.
+///
+/// Files are often read while they are still being saved, so after the initial run, a file that cannot be processed
+/// only results in a warning. Its previous scripts are kept until it changes again.
+fn update_watched_files(
+    config: &Config,
+    writer: &dyn OutputWriter,
+    watched_files: &mut BTreeMap<PathBuf, WatchedFile>,
+    initial: bool,
+) -> Result<bool, Wh2LuaError> {
+    let paths = watched_paths(config)?;
+    let mut changed = false;
+
+    let removed_paths: Vec<PathBuf> = watched_files
+        .keys()
+        .filter(|path| !paths.contains(path))
+        .cloned()
+        .collect();
+    for path in removed_paths {
+        Log::info(&format!("Removed: {}", path.display()));
+        watched_files.remove(&path);
+        changed = true;
+    }
+
+    for path in paths {
+        let modified = fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        if let Some(watched_file) = watched_files.get_mut(&path) {
+            if watched_file.modified == modified {
+                continue;
+            }
+            // Don't retry a failing file until it changes again
+            watched_file.modified = modified;
+        }
+
+        if !initial {
+            Log::info(&format!("Changed: {}", path.display()));
+        }
+
+        let known_skipped_tables = watched_files
+            .get(&path)
+            .map(|watched_file| watched_file.skipped_tables.clone())
+            .unwrap_or_default();
+        match render_tables(config, writer, &path, &known_skipped_tables) {
+            Ok((tables, skipped_tables)) => {
+                watched_files.insert(
+                    path,
+                    WatchedFile {
+                        modified,
+                        tables,
+                        skipped_tables,
+                    },
+                );
+                changed = true;
+            }
+            Err(error) if initial => return Err(error),
+            Err(error) => {
+                Log::warning(&format!(
+                    "Could not process {}, keeping its previous output. Problem was: {}",
+                    path.display(),
+                    error
+                ));
+                watched_files.entry(path).or_insert(WatchedFile {
+                    modified,
+                    tables: Vec::new(),
+                    skipped_tables: Vec::new(),
+                });
+            }
+        }
+    }
+
+    Ok(changed)
+}
+
+/// The packfiles from the config, or all extracted db files in the input directory
+fn watched_paths(config: &Config) -> Result<Vec<PathBuf>, Wh2LuaError> {
+    if config.packfiles.is_some() && config.packfiles.as_ref().unwrap().len() > 0 {
+        Ok(config.packfiles.as_ref().unwrap().clone())
+    } else if let Some(ref in_dir) = config.in_dir {
+        Ok(Rpfm::find_in_dir_db_files(in_dir))
+    } else {
+        Err(Wh2LuaError::ConfigError(format!(
+            "Neither packfile nor input directory parameters found in config and/or command arguments."
+        )))
+    }
+}
+
+/// Decodes and renders all db tables in a packfile, or a single extracted db file. Returns the rendered tables, and the
+/// tables that were skipped.
+///
+/// In strict mode, skipping a table that was not already skipped the last time the file was processed (e.g. because
+/// it has no definition in the schema) is an error.
+fn render_tables(
+    config: &Config,
+    writer: &dyn OutputWriter,
+    path: &Path,
+    known_skipped_tables: &[String],
+) -> Result<(Vec<RenderedTable>, Vec<String>), Wh2LuaError> {
+    // Only report what is skipped while processing this file
+    Log::clear_skipped();
+
+    let sources = if path
+        .extension()
+        .map_or(false, |extension| extension == "pack")
+    {
+        Rpfm::load_source(config, path)?
+    } else {
+        // Like a regular run, extracted db files are named after the input directory
+        let in_dir_name = config
+            .in_dir
+            .as_ref()
+            .and_then(|in_dir| in_dir.file_name())
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        vec![(
+            in_dir_name,
+            vec![Rpfm::pre_process_in_dir_file(config, path)?],
+        )]
+    };
+
+    let skipped_tables = Log::skipped_tables();
+    if config.strict {
+        let newly_skipped_tables: Vec<String> = skipped_tables
+            .iter()
+            .filter(|table| !known_skipped_tables.contains(table))
+            .cloned()
+            .collect();
+        if !newly_skipped_tables.is_empty() {
+            return Err(Wh2LuaError::TablesSkipped(newly_skipped_tables));
+        }
+    }
+
+    let mut result = Vec::new();
+    for (source, tables) in sources {
+        for table in tables {
+            let files = crate::render_table_files(config, writer, &table)?;
+            result.push(RenderedTable {
+                source: source.clone(),
+                table,
+                files,
+            });
+        }
+    }
+    Ok((result, skipped_tables))
+}
+
+/// Merges the files of all watched files in load order, like a regular run: if several packfiles generate the same
+/// file, the packfile that comes last wins. Includes the manifest, index and type stubs of the merged
+/// output.
+fn merge_scripts(
+    config: &Config,
+    watched_files: &BTreeMap<PathBuf, WatchedFile>,
+) -> Result<BTreeMap<Vec<String>, String>, Wh2LuaError> {
+    let mut result = BTreeMap::new();
+    let mut summary = OutputSummary::new(config);
+    let mut support_file_owners = crate::SupportFileOwners::new();
+    for path in watched_paths(config)? {
+        if let Some(watched_file) = watched_files.get(&path) {
+            for rendered in watched_file.tables.iter() {
+                crate::claim_support_files(
+                    &mut support_file_owners,
+                    &rendered.table,
+                    &rendered.files,
+                )?;
+                summary.add_table(&rendered.source, &rendered.table, &rendered.files);
+                result.extend(rendered.files.iter().cloned());
+            }
+        }
+    }
+    result.extend(summary.files(config));
+    Ok(result)
+}
+
+fn write_changed_scripts(
+    config: &Config,
+    previous_scripts: &BTreeMap<Vec<String>, String>,
+    scripts: &BTreeMap<Vec<String>, String>,
+) -> Result<(), Wh2LuaError> {
+    let mut sink = create_output_sink(config);
+
+    match config.sink {
+        // Archives are always saved as a whole
+        OutputSinkKind::Packfile | OutputSinkKind::Zip => {
+            for (path, script) in scripts.iter() {
+                sink.write_file(path, script)?;
+            }
+        }
+        OutputSinkKind::Directory | OutputSinkKind::Stdout => {
+            for (path, script) in scripts.iter() {
+                if previous_scripts.get(path) != Some(script) {
+                    Log::debug(&format!("Updating script: {}", path.join("/")));
+                    sink.write_file(path, script)?;
+                }
+            }
+            for path in previous_scripts
+                .keys()
+                .filter(|path| !scripts.contains_key(*path))
+                // With the Clean overwrite policy, these are removed below
+                .filter(|_| config.overwrite != OverwritePolicy::Clean)
+            {
+                Log::warning(&format!(
+                    "Script {} is no longer generated. Remove it manually if needed.",
+                    sink.display_path(path)
+                ));
+            }
+        }
+    }
+
+    sink.finish()?;
+
+    generated_files::update_generated_files(config, scripts.keys())?;
+
+    Log::info(&format!(
+        "{} script(s) updated",
+        scripts
+            .iter()
+            .filter(|(path, script)| previous_scripts.get(*path) != Some(*script))
+            .count()
+    ));
+    Ok(())
+}
@@ -6,14 +6,34 @@ use std::path::PathBuf;
 use csv;
 use rpfm_error;
 
+/// Process exit code when everything went fine
+pub const EXIT_CODE_SUCCESS: i32 = 0;
+/// Process exit code when output was generated, but packfiles or tables were skipped along the way. Kept apart from the
+/// error codes, and from 1, which is also used for command line usage errors.
+pub const EXIT_CODE_COMPLETED_WITH_WARNINGS: i32 = 10;
+
 #[derive(Debug)]
 pub enum Wh2LuaError {
     ConfigError(String),
     RpfmError(rpfm_error::Error),
     OutDirNotEmpty(PathBuf),
+    TablesSkipped(Vec<String>),
     IoError(std::io::Error),
 }
 
+impl Wh2LuaError {
+    /// Returns the process exit code for this category of error
+    pub fn exit_code(&self) -> i32 {
+        match &self {
+            &Wh2LuaError::ConfigError(_) => 2,
+            &Wh2LuaError::RpfmError(_) => 3,
+            &Wh2LuaError::OutDirNotEmpty(_) => 4,
+            &Wh2LuaError::TablesSkipped(_) => 5,
+            &Wh2LuaError::IoError(_) => 6,
+        }
+    }
+}
+
 impl From<std::io::Error> for Wh2LuaError {
     fn from(err: std::io::Error) -> Self {
         Wh2LuaError::IoError(err)
@@ -32,6 +52,12 @@ impl From<csv::Error> for Wh2LuaError {
     }
 }
 
+impl From<zip::result::ZipError> for Wh2LuaError {
+    fn from(err: zip::result::ZipError) -> Self {
+        Wh2LuaError::IoError(std::io::Error::new(std::io::ErrorKind::Other, err))
+    }
+}
+
 impl From<std::num::ParseIntError> for Wh2LuaError {
     fn from(err: std::num::ParseIntError) -> Self {
         Wh2LuaError::RpfmError(err.into())
@@ -49,7 +75,22 @@ impl fmt::Display for Wh2LuaError {
                 write!(f, "{}", err.to_terminal())
             }
             &Wh2LuaError::OutDirNotEmpty(path) => {
-                write!(f, "Output directory not empty: {}", path.display())
+                write!(
+                    f,
+                    "Output directory not empty: {} (use --merge or --clean to write into it anyway)",
+                    path.display()
+                )
+            }
+            &Wh2LuaError::TablesSkipped(tables) => {
+                write!(
+                    f,
+                    "Strict mode: {} db table(s) could not be processed:",
+                    tables.len()
+                )?;
+                for table in tables {
+                    write!(f, "\n  {}", table)?;
+                }
+                Ok(())
             }
             &Wh2LuaError::IoError(io_error) => {
                 write!(f, "Unexpected IO error: {}", io_error)